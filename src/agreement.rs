@@ -0,0 +1,155 @@
+use std::collections::{BTreeSet, HashMap};
+
+use analysis::{self, BlandAltman, Moments};
+use errors::*;
+use structs::*;
+use study::{Study, Mode};
+
+/// Number of Likert scale points
+const LEVELS: usize = 5;
+
+/// Experimenter's and crowd's rating of one surface on one dimension
+#[derive(Serialize)]
+pub struct SurfaceAgreement {
+    /// Episode date
+    pub date: Datestamp,
+    /// Episode flow type
+    pub flow: FlowType,
+    /// Episode number
+    #[serde(rename="number")]
+    pub num: u32,
+    /// Questionnaire dimension
+    pub question: &'static str,
+    /// Rating from the flow file
+    pub experimenter: u8,
+    /// Mean rating of the (non-excluded) participants
+    pub crowd: f64,
+    /// Number of participant ratings
+    pub ratings: u32,
+}
+
+impl SurfaceAgreement {
+    /// Crowd mean minus experimenter rating
+    pub fn difference(&self) -> f64 {
+        self.crowd - self.experimenter as f64
+    }
+}
+
+/// Agreement between experimenter and crowd on one dimension within one group of surfaces
+#[derive(Serialize)]
+pub struct Agreement {
+    /// Study ID
+    pub study: &'static str,
+    /// End-effector type (`None` for all surfaces of the study)
+    pub flow: Option<FlowType>,
+    /// Questionnaire dimension
+    pub question: &'static str,
+    /// Number of surfaces with both an experimenter rating and crowd ratings
+    pub surfaces: usize,
+    /// Pearson correlation of experimenter rating and crowd mean
+    pub pearson: f64,
+    /// Spearman correlation of experimenter rating and crowd mean
+    pub spearman: f64,
+    /// Mean absolute difference between experimenter rating and crowd mean
+    pub mad: f64,
+    /// Bland-Altman statistics (differences are crowd minus experimenter)
+    pub bland_altman: BlandAltman,
+    /// `confusion[e][c]` counts surfaces the experimenter rated `e + 1` and the crowd mean rounds to `c + 1`
+    pub confusion: Vec<Vec<u32>>,
+}
+
+/// Experimenter rating and crowd mean of every surface and dimension of a Likert study
+///
+/// Surfaces without an experimenter rating or without any participant ratings are left out.
+pub fn surfaces(study: &Study) -> Result<Vec<SurfaceAgreement>> {
+    let questions = study.config.questionnaire;
+    let excluded = study.excluded_users();
+
+    let mut moments = HashMap::new();
+    for (surface, user) in study.ratings()? {
+        if excluded.contains(&user) {
+            continue;
+        }
+        let answers = questions.iter()
+                               .map(|q| surface.ratings.get(q.short).map(|a| a.0 as f64))
+                               .collect::<Vec<_>>();
+        let entry = moments.entry((surface.date, surface.flow, surface.num))
+                           .or_insert_with(|| vec![Moments::default(); questions.len()]);
+        for (m, a) in entry.iter_mut().zip(answers) {
+            if let Some(a) = a {
+                m.push(a);
+            }
+        }
+    }
+
+    let mut rows = vec![];
+    for surface in &study.surfaces {
+        if let Some(crowd) = moments.get(&(surface.date, surface.flow, surface.num)) {
+            for (q, m) in questions.iter().zip(crowd) {
+                match surface.ratings.get(q.short) {
+                    Some(&Likert(experimenter)) if m.n > 0 => rows.push(SurfaceAgreement {
+                        date: surface.date,
+                        flow: surface.flow,
+                        num: surface.num,
+                        question: q.short,
+                        experimenter,
+                        crowd: m.mean,
+                        ratings: m.n,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Agreement statistics for one group of surfaces
+fn group(study: &Study, flow: Option<FlowType>, question: &'static str, rows: &[&SurfaceAgreement]) -> Agreement {
+    let pairs = rows.iter().map(|r| (r.experimenter as f64, r.crowd)).collect::<Vec<_>>();
+
+    let mut confusion = vec![vec![0; LEVELS]; LEVELS];
+    for r in rows {
+        let e = (r.experimenter as usize).max(1).min(LEVELS) - 1;
+        let c = (r.crowd.round() as usize).max(1).min(LEVELS) - 1;
+        confusion[e][c] += 1;
+    }
+
+    Agreement {
+        study: study.config.id,
+        flow,
+        question,
+        surfaces: rows.len(),
+        pearson: analysis::pearson(&pairs),
+        spearman: analysis::spearman(&pairs),
+        mad: rows.iter().map(|r| r.difference().abs()).sum::<f64>() / rows.len() as f64,
+        bland_altman: analysis::bland_altman(&pairs),
+        confusion,
+    }
+}
+
+/// Agreement between experimenter and crowd on every dimension of every Likert study, for all
+/// surfaces and for each end-effector type
+pub fn agreement(studies: &[Study]) -> Result<Vec<Agreement>> {
+    let mut results = vec![];
+    for study in studies {
+        match study.config.mode {
+            Mode::Likert => {}
+            _ => continue
+        }
+
+        let rows = surfaces(study)?;
+        let flows = rows.iter().map(|r| r.flow).collect::<BTreeSet<_>>();
+        for flow in Some(None).into_iter().chain(flows.into_iter().map(Some)) {
+            for question in study.config.questionnaire {
+                let group_rows = rows.iter()
+                                     .filter(|r| r.question == question.short && flow.map_or(true, |f| f == r.flow))
+                                     .collect::<Vec<_>>();
+                if !group_rows.is_empty() {
+                    results.push(group(study, flow, question.short, &group_rows));
+                }
+            }
+        }
+    }
+    Ok(results)
+}
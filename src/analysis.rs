@@ -0,0 +1,481 @@
+use rand::{Rng, SeedableRng};
+use rand::isaac::Isaac64Rng;
+
+/// Pseudo-comparisons against a reference item of strength 1, so that items that never win (or never
+/// lose) still get finite scores
+const BT_PRIOR: f64 = 0.5;
+
+/// Running mean and variance of one rating dimension (Welford's algorithm)
+#[derive(Clone, Default)]
+pub struct Moments {
+    /// Number of ratings
+    pub n: u32,
+    /// Mean rating
+    pub mean: f64,
+    /// Sum of squared deviations from the mean
+    m2: f64,
+}
+
+impl Moments {
+    /// Add one rating
+    pub fn push(&mut self, x: f64) {
+        self.n += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.n as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Sample variance (infinite with fewer than two ratings)
+    pub fn variance(&self) -> f64 {
+        if self.n < 2 {
+            ::std::f64::INFINITY
+        } else {
+            self.m2 / (self.n - 1) as f64
+        }
+    }
+
+    /// Width of the normal-approximation confidence interval for the mean, for a critical value `z`
+    pub fn ci_width(&self, z: f64) -> f64 {
+        2.0 * z * (self.variance() / self.n as f64).sqrt()
+    }
+}
+
+/// Fit a Bradley-Terry model to paired comparisons using Hunter's MM algorithm
+///
+/// `outcomes` are `(winner, loser)` pairs of item indices. Returns the log-strength of each item,
+/// relative to the reference item (so an item that was never compared scores 0).
+pub fn bradley_terry(n: usize, outcomes: &[(usize, usize)]) -> Vec<f64> {
+    let mut wins = vec![BT_PRIOR; n];
+    let mut opponents = vec![vec![]; n];
+    for &(w, l) in outcomes {
+        wins[w] += 1.0;
+        opponents[w].push(l);
+        opponents[l].push(w);
+    }
+
+    let mut strength = vec![1.0; n];
+    for _ in 0..1000 {
+        let next = (0..n).map(|i| {
+                             let denom = opponents[i].iter()
+                                                     .map(|&j| 1.0 / (strength[i] + strength[j]))
+                                                     .sum::<f64>()
+                                         + 2.0 * BT_PRIOR / (strength[i] + 1.0);
+                             wins[i] / denom
+                         })
+                         .collect::<Vec<_>>();
+        let change = next.iter()
+                         .zip(&strength)
+                         .map(|(a, b)| (a.ln() - b.ln()).abs())
+                         .fold(0.0, f64::max);
+        strength = next;
+        if change < 1e-9 {
+            break;
+        }
+    }
+
+    strength.into_iter().map(f64::ln).collect()
+}
+
+/// Agreement statistics for one category of a nominal labeling task
+#[derive(Serialize)]
+pub struct CategoryAgreement {
+    /// Number of times the category was chosen
+    pub labels: u32,
+    /// Number of items on which it was the most common label
+    pub majority: u32,
+    /// Proportion of rater pairs involving this category that agree on it (`None` if no pair involves it)
+    pub specific_agreement: Option<f64>,
+    /// Fleiss' category-specific kappa (`None` if the category was never or always chosen)
+    pub kappa: Option<f64>,
+}
+
+/// Fleiss' kappa for nominal labels, overall and per category
+///
+/// `counts[i][j]` is the number of raters who put item `i` in category `j`. Raters may differ in
+/// number between items; items with fewer than two labels only contribute to the label counts.
+pub fn fleiss_kappa(counts: &[Vec<u32>], categories: usize) -> (f64, Vec<CategoryAgreement>) {
+    let mut totals = vec![0.0; categories];
+    let mut pairs = 0.0; // sum of m(m-1) over items
+    let mut agreeing = 0.0; // sum of x(x-1) over items and categories
+    let mut agreeing_cat = vec![0.0; categories];
+    let mut involving_cat = vec![0.0; categories];
+    let mut disagreeing_cat = vec![0.0; categories];
+    let mut stats = (0..categories).map(|_| CategoryAgreement { labels: 0, majority: 0, specific_agreement: None, kappa: None })
+                                   .collect::<Vec<_>>();
+
+    for item in counts {
+        let m = item.iter().sum::<u32>() as f64;
+        for (j, &x) in item.iter().enumerate() {
+            stats[j].labels += x;
+        }
+        if let Some(&top) = item.iter().max() {
+            if top > 0 {
+                for (j, &x) in item.iter().enumerate() {
+                    if x == top {
+                        stats[j].majority += 1;
+                    }
+                }
+            }
+        }
+        if m < 2.0 {
+            continue;
+        }
+
+        pairs += m * (m - 1.0);
+        for (j, &x) in item.iter().enumerate() {
+            let x = x as f64;
+            totals[j] += x;
+            agreeing += x * (x - 1.0);
+            agreeing_cat[j] += x * (x - 1.0);
+            involving_cat[j] += x * (m - 1.0);
+            disagreeing_cat[j] += x * (m - x);
+        }
+    }
+
+    let n = totals.iter().sum::<f64>();
+    if pairs == 0.0 || n == 0.0 {
+        return (::std::f64::NAN, stats);
+    }
+
+    let p = totals.iter().map(|t| t / n).collect::<Vec<_>>();
+    let observed = agreeing / pairs;
+    let expected = p.iter().map(|p| p * p).sum::<f64>();
+    for j in 0..categories {
+        if involving_cat[j] > 0.0 {
+            stats[j].specific_agreement = Some(agreeing_cat[j] / involving_cat[j]);
+        }
+        let chance = pairs * p[j] * (1.0 - p[j]);
+        if chance > 0.0 {
+            stats[j].kappa = Some(1.0 - disagreeing_cat[j] / chance);
+        }
+    }
+    if expected >= 1.0 {
+        // every label is in the same category, so agreement beyond chance is undefined
+        return (::std::f64::NAN, stats);
+    }
+
+    ((observed - expected) / (1.0 - expected), stats)
+}
+
+/// Point estimate with a bootstrap confidence interval
+#[derive(Serialize, Clone, Copy)]
+pub struct Estimate {
+    /// Statistic computed on the full sample
+    pub value: f64,
+    /// Lower confidence limit
+    pub low: f64,
+    /// Upper confidence limit
+    pub high: f64,
+}
+
+/// Krippendorff's alpha with the ordinal difference function
+///
+/// Each unit holds the values (in `0..levels`) assigned to it by different raters; units with
+/// fewer than two values are not pairable and are ignored.
+pub fn krippendorff_alpha_ordinal(units: &[&[usize]], levels: usize) -> f64 {
+    // coincidence matrix
+    let mut o = vec![vec![0.0; levels]; levels];
+    for unit in units {
+        let m = unit.len() as f64;
+        if m < 2.0 {
+            continue;
+        }
+        for (i, &c) in unit.iter().enumerate() {
+            for (j, &k) in unit.iter().enumerate() {
+                if i != j {
+                    o[c][k] += 1.0 / (m - 1.0);
+                }
+            }
+        }
+    }
+
+    let marginals = o.iter().map(|row| row.iter().sum::<f64>()).collect::<Vec<_>>();
+    let n = marginals.iter().sum::<f64>();
+    if n < 2.0 {
+        return ::std::f64::NAN;
+    }
+
+    let delta2 = |c: usize, k: usize| {
+        let (lo, hi) = if c < k { (c, k) } else { (k, c) };
+        let d = marginals[lo..hi + 1].iter().sum::<f64>() - (marginals[c] + marginals[k]) / 2.0;
+        d * d
+    };
+
+    let mut observed = 0.0;
+    let mut expected = 0.0;
+    for c in 0..levels {
+        for k in 0..levels {
+            let d = delta2(c, k);
+            observed += o[c][k] * d;
+            expected += marginals[c] * marginals[k] * d;
+        }
+    }
+    if expected == 0.0 {
+        return ::std::f64::NAN;
+    }
+    1.0 - (n - 1.0) * observed / expected
+}
+
+/// ICC(2,k): two-way random effects, absolute agreement, reliability of the mean of k raters
+///
+/// `rows` is a complete subjects × raters matrix (every row has the same length k).
+pub fn icc_2k(rows: &[&[f64]]) -> f64 {
+    let n = rows.len();
+    let k = rows.first().map_or(0, |r| r.len());
+    if n < 2 || k < 2 {
+        return ::std::f64::NAN;
+    }
+    let (nf, kf) = (n as f64, k as f64);
+
+    let grand = rows.iter().map(|r| r.iter().sum::<f64>()).sum::<f64>() / (nf * kf);
+    let ss_rows = rows.iter()
+                      .map(|r| (r.iter().sum::<f64>() / kf - grand).powi(2))
+                      .sum::<f64>() * kf;
+    let ss_cols = (0..k).map(|j| (rows.iter().map(|r| r[j]).sum::<f64>() / nf - grand).powi(2))
+                        .sum::<f64>() * nf;
+    let ss_total = rows.iter()
+                       .flat_map(|r| r.iter())
+                       .map(|x| (x - grand).powi(2))
+                       .sum::<f64>();
+
+    let ms_rows = ss_rows / (nf - 1.0);
+    let ms_cols = ss_cols / (kf - 1.0);
+    let ms_error = (ss_total - ss_rows - ss_cols) / ((nf - 1.0) * (kf - 1.0));
+    (ms_rows - ms_error) / (ms_rows + (ms_cols - ms_error) / nf)
+}
+
+/// Ranks of a sample (starting at 1, with ties given their average rank)
+fn ranks(xs: &[f64]) -> Vec<f64> {
+    let mut order = (0..xs.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| xs[a].partial_cmp(&xs[b]).unwrap());
+
+    let mut ranks = vec![0.0; xs.len()];
+    let mut i = 0;
+    while i < order.len() {
+        let mut j = i;
+        while j + 1 < order.len() && xs[order[j + 1]] == xs[order[i]] {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        for &o in &order[i..j + 1] {
+            ranks[o] = rank;
+        }
+        i = j + 1;
+    }
+    ranks
+}
+
+/// Spearman's rank correlation of paired observations (NaN if either side is constant)
+pub fn spearman(pairs: &[(f64, f64)]) -> f64 {
+    let n = pairs.len() as f64;
+    let xs = ranks(&pairs.iter().map(|p| p.0).collect::<Vec<_>>());
+    let ys = ranks(&pairs.iter().map(|p| p.1).collect::<Vec<_>>());
+    let (mx, my) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+
+    let cov = xs.iter().zip(&ys).map(|(x, y)| (x - mx) * (y - my)).sum::<f64>();
+    let vx = xs.iter().map(|x| (x - mx).powi(2)).sum::<f64>();
+    let vy = ys.iter().map(|y| (y - my).powi(2)).sum::<f64>();
+    cov / (vx * vy).sqrt()
+}
+
+/// Median of a sample (NaN if it is empty)
+pub fn median(xs: &[f64]) -> f64 {
+    let mut xs = xs.to_vec();
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    match xs.len() {
+        0 => ::std::f64::NAN,
+        n if n % 2 == 1 => xs[n / 2],
+        n => (xs[n / 2 - 1] + xs[n / 2]) / 2.0
+    }
+}
+
+/// Pearson's correlation of paired observations (NaN if either side is constant)
+pub fn pearson(pairs: &[(f64, f64)]) -> f64 {
+    let n = pairs.len() as f64;
+    let (mx, my) = (pairs.iter().map(|p| p.0).sum::<f64>() / n, pairs.iter().map(|p| p.1).sum::<f64>() / n);
+
+    let cov = pairs.iter().map(|&(x, y)| (x - mx) * (y - my)).sum::<f64>();
+    let vx = pairs.iter().map(|&(x, _)| (x - mx).powi(2)).sum::<f64>();
+    let vy = pairs.iter().map(|&(_, y)| (y - my).powi(2)).sum::<f64>();
+    cov / (vx * vy).sqrt()
+}
+
+/// Bland-Altman summary of the differences between two measurements of the same items
+#[derive(Serialize, Clone, Copy)]
+pub struct BlandAltman {
+    /// Mean difference (second minus first)
+    pub bias: f64,
+    /// Standard deviation of the differences
+    pub sd: f64,
+    /// Lower 95% limit of agreement
+    pub lower: f64,
+    /// Upper 95% limit of agreement
+    pub upper: f64,
+}
+
+/// Bland-Altman statistics of paired measurements `(a, b)`, with differences taken as `b - a`
+///
+/// The bias is NaN without any pairs, and the SD and limits of agreement with fewer than two.
+pub fn bland_altman(pairs: &[(f64, f64)]) -> BlandAltman {
+    let mut moments = Moments::default();
+    for &(a, b) in pairs {
+        moments.push(b - a);
+    }
+    let sd = if pairs.len() < 2 { ::std::f64::NAN } else { moments.variance().sqrt() };
+    BlandAltman {
+        bias: if pairs.is_empty() { ::std::f64::NAN } else { moments.mean },
+        sd,
+        lower: moments.mean - 1.96 * sd,
+        upper: moments.mean + 1.96 * sd,
+    }
+}
+
+/// Evaluate a statistic on a sample, with a percentile bootstrap confidence interval
+///
+/// The items are resampled with replacement `samples` times from a fixed seed, so repeated runs
+/// give the same interval. Resamples on which the statistic is undefined (NaN) are dropped.
+pub fn bootstrap<T, F>(items: &[T], samples: usize, confidence: f64, seed: u64, stat: F) -> Estimate
+    where F: Fn(&[&T]) -> f64
+{
+    let all = items.iter().collect::<Vec<_>>();
+    let value = stat(&all);
+    if items.is_empty() {
+        return Estimate { value, low: ::std::f64::NAN, high: ::std::f64::NAN };
+    }
+
+    let mut rng = Isaac64Rng::from_seed(&[seed][..]);
+    let mut stats = (0..samples).map(|_| {
+                                    let resample = (0..items.len()).map(|_| &items[rng.gen_range(0, items.len())])
+                                                                   .collect::<Vec<_>>();
+                                    stat(&resample)
+                                })
+                                .filter(|x| !x.is_nan())
+                                .collect::<Vec<_>>();
+    if stats.is_empty() {
+        return Estimate { value, low: ::std::f64::NAN, high: ::std::f64::NAN };
+    }
+    stats.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let tail = (1.0 - confidence) / 2.0;
+    let at = |q: f64| stats[((q * stats.len() as f64) as usize).min(stats.len() - 1)];
+    Estimate { value, low: at(tail), high: at(1.0 - tail) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    #[test]
+    fn krippendorff_reference() {
+        // Krippendorff (2011), "Computing Krippendorff's alpha-reliability", example C: 4 observers,
+        // 12 units, values 1-5 (shifted to 0-4 here), with missing values; ordinal alpha = 0.815
+        let units: &[&[usize]] = &[&[0, 0, 0], &[1, 1, 2, 1], &[2, 2, 2, 2], &[2, 2, 2, 2],
+                                   &[1, 1, 1, 1], &[0, 1, 2, 3], &[3, 3, 3, 3], &[0, 0, 1, 0],
+                                   &[1, 1, 1, 1], &[4, 4, 4], &[0, 0], &[2]];
+        let alpha = krippendorff_alpha_ordinal(units, 5);
+        assert!(close(alpha, 0.815, 5e-4), "alpha = {}", alpha);
+    }
+
+    #[test]
+    fn krippendorff_undefined() {
+        assert!(krippendorff_alpha_ordinal(&[&[1], &[2]], 3).is_nan());
+        assert!(krippendorff_alpha_ordinal(&[&[1, 1], &[1, 1]], 3).is_nan());
+    }
+
+    #[test]
+    fn icc_reference() {
+        // Shrout & Fleiss (1979), table 2: 6 targets rated by 4 judges; ICC(2,4) = .62
+        let rows: &[&[f64]] = &[&[9.0, 2.0, 5.0, 8.0], &[6.0, 1.0, 3.0, 2.0], &[8.0, 4.0, 6.0, 8.0],
+                                &[7.0, 1.0, 2.0, 6.0], &[10.0, 5.0, 6.0, 9.0], &[6.0, 2.0, 4.0, 7.0]];
+        let icc = icc_2k(rows);
+        assert!(close(icc, 0.62, 5e-3), "ICC = {}", icc);
+        assert!(icc_2k(&rows[..1]).is_nan());
+    }
+
+    #[test]
+    fn spearman_reference() {
+        // IQ against hours of TV per week (the worked example on Wikipedia): rho = -29/165
+        let pairs = [(106.0, 7.0), (86.0, 0.0), (100.0, 27.0), (101.0, 50.0), (99.0, 28.0),
+                     (103.0, 29.0), (97.0, 20.0), (113.0, 12.0), (112.0, 6.0), (110.0, 17.0)];
+        assert!(close(spearman(&pairs), -29.0 / 165.0, 1e-12));
+    }
+
+    #[test]
+    fn spearman_ties() {
+        assert_eq!(ranks(&[3.0, 1.0, 3.0, 2.0]), vec![3.5, 1.0, 3.5, 2.0]);
+        assert!(close(spearman(&[(1.0, 1.0), (2.0, 2.0), (2.0, 2.0), (3.0, 3.0)]), 1.0, 1e-12));
+        assert!(spearman(&[(1.0, 5.0), (2.0, 5.0), (3.0, 5.0)]).is_nan());
+    }
+
+    #[test]
+    fn fleiss_reference() {
+        // Fleiss (1971) style example from Wikipedia: 10 items, 14 raters, 5 categories; kappa = 0.210
+        let counts = vec![vec![0, 0, 0, 0, 14], vec![0, 2, 6, 4, 2], vec![0, 0, 3, 5, 6], vec![0, 3, 9, 2, 0],
+                          vec![2, 2, 8, 1, 1], vec![7, 7, 0, 0, 0], vec![3, 2, 6, 3, 0], vec![2, 5, 3, 2, 2],
+                          vec![6, 5, 2, 1, 0], vec![0, 2, 2, 3, 7]];
+        let (kappa, stats) = fleiss_kappa(&counts, 5);
+        assert!(close(kappa, 0.210, 5e-4), "kappa = {}", kappa);
+        assert_eq!(stats.iter().map(|s| s.labels).collect::<Vec<_>>(), vec![20, 28, 39, 21, 32]);
+        assert!(stats.iter().all(|s| s.kappa.is_some() && s.specific_agreement.is_some()));
+    }
+
+    #[test]
+    fn fleiss_unused_category() {
+        let (kappa, stats) = fleiss_kappa(&[vec![2, 0, 0], vec![0, 2, 0]], 3);
+        assert!(close(kappa, 1.0, 1e-12));
+        assert!(stats[2].kappa.is_none() && stats[2].specific_agreement.is_none());
+
+        let (kappa, stats) = fleiss_kappa(&[vec![3, 0], vec![2, 0]], 2);
+        assert!(kappa.is_nan());
+        assert!(stats[0].kappa.is_none());
+    }
+
+    #[test]
+    fn bradley_terry_symmetric() {
+        // one win each way is the fixed point where both items are as strong as the reference
+        let scores = bradley_terry(3, &[(0, 1), (1, 0)]);
+        assert!(scores.iter().all(|s| close(*s, 0.0, 1e-6)), "scores = {:?}", scores);
+    }
+
+    #[test]
+    fn bradley_terry_order() {
+        let scores = bradley_terry(3, &[(0, 1), (0, 1), (0, 2), (1, 2), (1, 2), (2, 0)]);
+        assert!(scores[0] > scores[1] && scores[1] > scores[2], "scores = {:?}", scores);
+        assert!(scores.iter().all(|s| s.is_finite()));
+        // an item that was never compared keeps the reference strength
+        assert_eq!(bradley_terry(3, &[(0, 1)])[2], 0.0);
+    }
+
+    #[test]
+    fn bland_altman_small_samples() {
+        let one = bland_altman(&[(1.0, 3.0)]);
+        assert_eq!(one.bias, 2.0);
+        assert!(one.sd.is_nan() && one.lower.is_nan() && one.upper.is_nan());
+        assert!(bland_altman(&[]).bias.is_nan());
+
+        let two = bland_altman(&[(1.0, 2.0), (1.0, 4.0)]);
+        assert!(close(two.sd, 2.0f64.sqrt(), 1e-12));
+    }
+
+    #[test]
+    fn bootstrap_interval() {
+        let mean = |xs: &[&f64]| xs.iter().map(|&&x| x).sum::<f64>() / xs.len() as f64;
+        let items = (0..50).map(|i| i as f64).collect::<Vec<_>>();
+        let estimate = bootstrap(&items, 1000, 0.95, 42, &mean);
+        assert_eq!(estimate.value, 24.5);
+        assert!(estimate.low < 24.5 && 24.5 < estimate.high);
+        // standard error of the mean is about 2.04, so the interval is about 8 wide
+        assert!(close(estimate.high - estimate.low, 8.0, 1.5), "{} - {}", estimate.low, estimate.high);
+
+        let again = bootstrap(&items, 1000, 0.95, 42, &mean);
+        assert_eq!((again.low, again.high), (estimate.low, estimate.high));
+
+        let constant = bootstrap(&[3.0; 10], 100, 0.95, 1, &mean);
+        assert_eq!((constant.value, constant.low, constant.high), (3.0, 3.0, 3.0));
+        assert!(bootstrap(&[] as &[f64], 100, 0.95, 1, &mean).low.is_nan());
+    }
+}
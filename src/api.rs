@@ -0,0 +1,265 @@
+use rocket::State;
+use rocket::http::{Cookies, Status};
+use rocket::http::uri::URI;
+use rocket::request::Form;
+use rocket_contrib::{Json, Value};
+
+use auth::Admin;
+use errors::*;
+use participants::Participants;
+use routes;
+use settings;
+use structs::*;
+use study::{Study, Studies, Mode};
+
+/// Episode key and image URL of a surface (without the experimenter's ratings)
+fn surface_json(date: Datestamp, flow: FlowType, num: u32) -> Value {
+    json!({
+        "date": date,
+        "flow": flow,
+        "number": num,
+        "image": format!("/image/{}/{}/{}", date, flow, num)
+    })
+}
+
+/// Progress of a participant through a study
+fn progress(study: &Study, user: &User) -> Value {
+    let fresh = UserInfo::default();
+    let users = study.users.lock().unwrap();
+    let info = users.get(&user.id).unwrap_or(&fresh);
+    let quota = study.config.crowdsourcing.as_ref().map(|c| c.quota);
+
+    json!({
+        "id": user.id,
+        "name": user.name,
+        "onboarding": routes::onboarding_step(info),
+        "practiced": info.practiced,
+        "practice": study.practice.len(),
+        "trials": info.trials(),
+        "quota": quota,
+        "remaining": quota.map(|q| q.saturating_sub(info.trials())),
+        "checks_passed": info.checks_passed,
+        "checks_failed": info.checks_failed,
+        "completed": info.completed
+    })
+}
+
+/// Status of a participant who has no trials left (crowdsourcing participants get their completion code)
+fn finished(study: &Study, user: &User) -> Result<Value> {
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    Ok(match study.config.crowdsourcing {
+        Some(ref crowd) => {
+            let (code, url) = routes::completion(study, user, user_info, crowd)?;
+            json!({ "status": "complete", "code": code, "url": url })
+        }
+        None => json!({ "status": "finished" })
+    })
+}
+
+handle! {
+    #[post("/login", data="<form>")]
+    pub fn login(mut cookies: Cookies, participants: State<Participants>, form: Form<ApiLogin>) -> Json {
+        let ApiLogin { user_name, worker } = form.into_inner();
+        let id = routes::sign_in(&mut cookies, &participants, &user_name, worker.as_ref().map(|w| &w[..]))?
+                        .ok_or(ErrorKind::BadParam("name already taken"))?;
+        Ok(Json(json!({ "id": id, "name": participants.name(&id) })))
+    }
+}
+
+handle! {
+    #[get("/study/<id>")]
+    pub fn config(studies: State<Studies>, id: String) -> Json {
+        let study = Study::find(&studies, &id)?;
+        let (mode, categories, describe, size) = match study.config.mode {
+            Mode::Likert => ("likert", &[][..], false, None),
+            Mode::Pairwise => ("pairwise", &[][..], false, None),
+            Mode::Ranking { size } => ("ranking", &[][..], false, Some(size)),
+            Mode::Labeling { categories, describe } => ("labeling", categories, describe, None),
+        };
+
+        Ok(Json(json!({
+            "id": study.config.id,
+            "title": study.config.title,
+            "mode": mode,
+            "questionnaire": study.config.questionnaire,
+            "categories": categories,
+            "describe": describe,
+            "ranking_size": size,
+            "practice": study.practice.len(),
+            "quota": study.config.crowdsourcing.as_ref().map(|c| c.quota),
+            "consent_version": settings::CONSENT_VERSION,
+            "demographics": settings::DEMOGRAPHICS
+        })))
+    }
+}
+
+handle_api_login! {
+    #[get("/study/<id>/next")]
+    pub fn next/next_login(user: User, studies: State<Studies>, id: String) -> Json {
+        let study = Study::find(&studies, &id)?;
+        {
+            let mut users = study.users.lock().unwrap();
+            routes::ensure_order(study, &mut users, &user)?;
+            let user_info = users.get_mut(&user.id).unwrap();
+            if let Some(step) = routes::onboarding_step(user_info) {
+                return Ok(Json(json!({ "status": "onboarding", "step": step })));
+            }
+            if let Some(ref crowd) = study.config.crowdsourcing {
+                if user_info.trials() >= crowd.quota {
+                    let (code, url) = routes::completion(study, &user, user_info, crowd)?;
+                    return Ok(Json(json!({ "status": "complete", "code": code, "url": url })));
+                }
+            }
+            if let Some(surface) = study.practice.get(user_info.practiced) {
+                return Ok(Json(json!({
+                    "status": "practice",
+                    "surface": surface_json(surface.date, surface.flow, surface.num),
+                    "remaining": study.practice.len() - user_info.practiced
+                })));
+            }
+        }
+        let indexed = |i: usize| surface_json(study.surfaces[i].date, study.surfaces[i].flow, study.surfaces[i].num);
+        let kind = match study.config.mode {
+            Mode::Likert => "rating",
+            Mode::Labeling { .. } => "label",
+            Mode::Pairwise => {
+                return Ok(Json(match routes::choose_pair(study, &user)? {
+                    Some((a, b)) => json!({
+                        "status": "trial",
+                        "kind": "pair",
+                        "a": indexed(a),
+                        "b": indexed(b)
+                    }),
+                    None => finished(study, &user)?
+                }));
+            }
+            Mode::Ranking { size } => {
+                return Ok(Json(match routes::choose_ranking(study, &user, size)? {
+                    Some((shown, question)) => json!({
+                        "status": "trial",
+                        "kind": "ranking",
+                        "question": question.short,
+                        "surfaces": shown.iter().map(|&i| indexed(i)).collect::<Vec<_>>()
+                    }),
+                    None => finished(study, &user)?
+                }));
+            }
+        };
+
+        let (date, flow, num) = match routes::choose_surface(study, &user)? {
+            Some(key) => key,
+            None => return Ok(Json(finished(study, &user)?))
+        };
+        let attention = study.users.lock().unwrap()[&user.id].check.as_ref().and_then(|c| c.attention());
+        Ok(Json(json!({
+            "status": "trial",
+            "kind": kind,
+            "surface": surface_json(date, flow, num),
+            "attention": attention
+        })))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/onboarding/<step>", data="<form>")]
+    pub fn onboarding/onboarding_login(user: User, studies: State<Studies>, id: String, step: String, form: Form<Answers>) -> Json {
+        let Answers(answers) = form.into_inner();
+        let study = Study::find(&studies, &id)?;
+        {
+            let mut users = study.users.lock().unwrap();
+            routes::ensure_order(study, &mut users, &user)?;
+            let user_info = users.get_mut(&user.id).unwrap();
+            if let Some(error) = routes::record_onboarding(study, &user, user_info, &step, &answers)? {
+                Err::<(), _>(ErrorKind::BadParam(error))?;
+            }
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/practice", data="<form>")]
+    pub fn practice/practice_login(user: User, studies: State<Studies>, id: String, form: Form<SurfaceData>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        let mut users = study.users.lock().unwrap();
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        let feedback = routes::record_practice(study, &user, user_info, form.into_inner())?
+                              .ok_or(ErrorKind::BadParam("all ratings are required"))?;
+        Ok(Json(json!({
+            "feedback": feedback,
+            "remaining": study.practice.len() - user_info.practiced
+        })))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/rate", data="<form>")]
+    pub fn rate/rate_login(user: User, studies: State<Studies>, id: String, form: Form<SurfaceData>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        if !routes::record_rating(study, &user, form.into_inner())? {
+            Err::<(), _>(ErrorKind::BadParam("all ratings are required"))?;
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/label", data="<form>")]
+    pub fn label/label_login(user: User, studies: State<Studies>, id: String, form: Form<Label>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        if !routes::record_label(study, &user, form.into_inner())? {
+            Err::<(), _>(ErrorKind::BadParam("a known category is required"))?;
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/compare", data="<form>")]
+    pub fn compare/compare_login(user: User, studies: State<Studies>, id: String, form: Form<Comparison>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        if !routes::record_comparison(study, &user, form.into_inner())? {
+            Err::<(), _>(ErrorKind::BadParam("all questions are required"))?;
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/rank", data="<form>")]
+    pub fn rank/rank_login(user: User, studies: State<Studies>, id: String, form: Form<RankingForm>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        if !routes::record_ranking(study, &user, &form.into_inner())? {
+            Err::<(), _>(ErrorKind::BadParam("every surface must be ranked"))?;
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[post("/study/<id>/report", data="<form>")]
+    pub fn report/report_login(user: User, studies: State<Studies>, id: String, form: Form<Report>) -> Json {
+        let study = Study::find(&studies, &id)?;
+        if !routes::record_report(study, &user, form.into_inner())? {
+            Err::<(), _>(ErrorKind::BadParam("at least one reason is required"))?;
+        }
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[get("/study/<id>/progress")]
+    pub fn study_progress/study_progress_login(user: User, studies: State<Studies>, id: String) -> Json {
+        let study = Study::find(&studies, &id)?;
+        Ok(Json(progress(study, &user)))
+    }
+}
+
+handle_api_login! {
+    #[get("/study/<id>/surfaces")]
+    pub fn surfaces/surfaces_login(admin: Admin, studies: State<Studies>, id: String, query: ListQuery) -> Json {
+        let study = Study::find(&studies, &id)?;
+        Ok(Json(routes::surface_page(study, &query)))
+    }
+}
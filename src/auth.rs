@@ -0,0 +1,79 @@
+use ring::{digest, pbkdf2};
+use ring::rand::{SecureRandom, SystemRandom};
+use rocket::request::{Request, FromRequest, Outcome};
+use rocket::outcome::IntoOutcome;
+
+use settings;
+
+static DIGEST_ALG: &digest::Algorithm = &digest::SHA256;
+const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Administrator account (see `settings::ADMINS`)
+pub struct AdminAccount {
+    /// Login name
+    pub name: &'static str,
+    /// Random salt, hex-encoded
+    pub salt: &'static str,
+    /// PBKDF2-HMAC-SHA256 hash of the password, hex-encoded
+    pub hash: &'static str,
+}
+
+/// Logged-in administrator (request guard for management and analysis pages)
+pub struct Admin {
+    /// Login name of the administrator
+    pub name: String
+}
+
+impl AdminAccount {
+    /// Check a password against this account's hash
+    pub fn verify(&self, password: &str) -> bool {
+        match (from_hex(self.salt), from_hex(self.hash)) {
+            (Some(salt), Some(hash)) => pbkdf2::verify(DIGEST_ALG, PBKDF2_ITERATIONS, &salt, password.as_bytes(), &hash).is_ok(),
+            _ => false
+        }
+    }
+}
+
+impl Admin {
+    /// Look up an administrator by name and password
+    pub fn login(name: &str, password: &str) -> Option<Admin> {
+        settings::ADMINS.iter()
+                        .find(|a| a.name == name && a.verify(password))
+                        .map(|a| Admin { name: a.name.to_owned() })
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Admin, ()> {
+        request.cookies()
+               .get_private("admin")
+               .map(|c| c.value().to_owned())
+               .and_then(|name| settings::ADMINS.iter().find(|a| a.name == name).map(|a| Admin { name: a.name.to_owned() }))
+               .or_forward(())
+    }
+}
+
+/// Generate a salt and hash for a new password, hex-encoded for pasting into `settings::ADMINS`
+pub fn hash_password(password: &str) -> (String, String) {
+    let mut salt = [0; SALT_LEN];
+    SystemRandom::new().fill(&mut salt).unwrap();
+    let mut hash = [0; CREDENTIAL_LEN];
+    pbkdf2::derive(DIGEST_ALG, PBKDF2_ITERATIONS, &salt, password.as_bytes(), &mut hash);
+    (to_hex(&salt), to_hex(&hash))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len() / 2).map(|i| s.get(2 * i..2 * i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+                    .collect()
+}
@@ -0,0 +1,39 @@
+use rocket::http::Status;
+use rocket::request::Request;
+
+use errors::*;
+
+/// Respond to an error raised by Rocket itself (no matching route, a failing request guard, ...)
+fn caught(status: Status, req: &Request) -> ErrorResponse {
+    ErrorResponse::log(status, explanation(status).to_owned(), format!("{} {}", req.method(), req.uri()))
+}
+
+#[error(400)]
+pub fn bad_request(req: &Request) -> ErrorResponse {
+    caught(Status::BadRequest, req)
+}
+
+#[error(401)]
+pub fn unauthorized(req: &Request) -> ErrorResponse {
+    caught(Status::Unauthorized, req)
+}
+
+#[error(403)]
+pub fn forbidden(req: &Request) -> ErrorResponse {
+    caught(Status::Forbidden, req)
+}
+
+#[error(404)]
+pub fn not_found(req: &Request) -> ErrorResponse {
+    caught(Status::NotFound, req)
+}
+
+#[error(422)]
+pub fn unprocessable_entity(req: &Request) -> ErrorResponse {
+    caught(Status::UnprocessableEntity, req)
+}
+
+#[error(500)]
+pub fn internal_error(req: &Request) -> ErrorResponse {
+    caught(Status::InternalServerError, req)
+}
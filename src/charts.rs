@@ -0,0 +1,72 @@
+use std::fmt::Write;
+
+/// Size of a chart in pixels
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 220.0;
+
+/// Space for the axis labels
+const LEFT: f64 = 48.0;
+const BOTTOM: f64 = 36.0;
+const TOP: f64 = 12.0;
+
+/// At most this many category labels are written under the bars
+const MAX_LABELS: usize = 16;
+
+/// Escape text for inclusion in SVG markup
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Round an axis maximum up to 1, 2 or 5 times a power of ten
+fn nice_max(x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10f64.powf(x.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter()
+                         .map(|m| m * magnitude)
+                         .find(|&m| m >= x)
+                         .unwrap()
+}
+
+/// Vertical bar chart as an inline SVG element
+///
+/// `labels` name the bars (only some are drawn if there are many); `y_label` describes the values.
+pub fn bar_chart(labels: &[String], values: &[f64], y_label: &str) -> String {
+    let max = nice_max(values.iter().cloned().fold(0.0, f64::max));
+    let plot_w = WIDTH - LEFT;
+    let plot_h = HEIGHT - BOTTOM - TOP;
+    let slot = plot_w / values.len().max(1) as f64;
+    let label_every = (labels.len() + MAX_LABELS - 1) / MAX_LABELS;
+
+    let mut svg = String::new();
+    write!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" font-family="sans-serif" font-size="10">"#,
+           w = WIDTH, h = HEIGHT).unwrap();
+
+    // horizontal grid lines with their values
+    for i in 0..5 {
+        let v = max * i as f64 / 4.0;
+        let y = TOP + plot_h * (1.0 - v / max);
+        write!(svg, r##"<line x1="{}" y1="{y}" x2="{}" y2="{y}" stroke="#ddd"/><text x="{}" y="{}" text-anchor="end">{}</text>"##,
+               LEFT, WIDTH, LEFT - 4.0, y + 3.0, v, y = y).unwrap();
+    }
+    write!(svg, r#"<text x="10" y="{}" transform="rotate(-90 10 {})" text-anchor="middle">{}</text>"#,
+           TOP + plot_h / 2.0, TOP + plot_h / 2.0, escape(y_label)).unwrap();
+
+    for (i, &v) in values.iter().enumerate() {
+        let x = LEFT + slot * i as f64;
+        let h = plot_h * v / max;
+        write!(svg, r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="#4a7ab5"><title>{}: {}</title></rect>"##,
+               x + slot * 0.1, TOP + plot_h - h, slot * 0.8, h,
+               labels.get(i).map_or(String::new(), |l| escape(l)), v).unwrap();
+        if i % label_every.max(1) == 0 {
+            if let Some(label) = labels.get(i) {
+                write!(svg, r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+                       x + slot / 2.0, HEIGHT - BOTTOM + 14.0, escape(label)).unwrap();
+            }
+        }
+    }
+    write!(svg, r#"<line x1="{l}" y1="{b}" x2="{w}" y2="{b}" stroke="black"/></svg>"#,
+           l = LEFT, b = TOP + plot_h, w = WIDTH).unwrap();
+    svg
+}
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use charts;
+use errors::*;
+use study::Study;
+use utils::*;
+
+/// Overview of a study's progress, with charts as inline SVGs
+#[derive(Serialize)]
+pub struct Dashboard {
+    /// Main-phase trials so far
+    pub trials: usize,
+    /// Raters with at least one main-phase trial
+    pub raters: usize,
+    /// Raters active in the last 24 hours
+    pub active_day: usize,
+    /// Raters active in the last 7 days
+    pub active_week: usize,
+    /// Surfaces in the study
+    pub surfaces: usize,
+    /// Surfaces without any rating
+    pub unrated: usize,
+    /// Ratings (main-phase Likert responses, not practice, gold checks or reports) per day
+    pub ratings_per_day: String,
+    /// Distinct raters per day
+    pub raters_per_day: String,
+    /// Histogram of the number of ratings per surface
+    pub coverage: String,
+    /// Distribution of the answers to each question
+    pub distributions: Vec<Distribution>,
+    /// Number of reports giving each reason
+    pub reports: String,
+}
+
+/// Chart of the answers to one question
+#[derive(Serialize)]
+pub struct Distribution {
+    /// Short name of the question
+    pub question: &'static str,
+    /// Number of answers at each scale point
+    pub chart: String,
+}
+
+/// Compute the dashboard of a study from its output files
+pub fn dashboard(study: &Study) -> Result<Dashboard> {
+    let now = timestamp();
+    let questions = study.config.questionnaire;
+
+    let mut days = BTreeMap::<String, (u32, HashSet<String>)>::new();
+    let mut raters = HashSet::new();
+    let mut active_day = HashSet::new();
+    let mut active_week = HashSet::new();
+    let mut trials = 0;
+    for row in study.trials()? {
+        if row.response == "practice" {
+            continue;
+        }
+        trials += 1;
+        if row.time + 24 * 3600 > now {
+            active_day.insert(row.user.clone());
+        }
+        if row.time + 7 * 24 * 3600 > now {
+            active_week.insert(row.user.clone());
+        }
+        raters.insert(row.user.clone());
+        let day = days.entry(day_of(row.time)).or_insert_with(Default::default);
+        if row.response == "rating" {
+            day.0 += 1;
+        }
+        day.1.insert(row.user);
+    }
+    let day_labels = days.keys().cloned().collect::<Vec<_>>();
+
+    let mut per_surface = HashMap::new();
+    let mut answers = vec![vec![0; 5]; questions.len()];
+    for (surface, _) in study.ratings()? {
+        *per_surface.entry((surface.date, surface.flow, surface.num)).or_insert(0) += 1;
+        for (q, question) in questions.iter().enumerate() {
+            if let Some(a) = surface.ratings.get(question.short) {
+                answers[q][a.0 as usize - 1] += 1;
+            }
+        }
+    }
+    let counts = study.surfaces.iter()
+                               .map(|s| per_surface.get(&(s.date, s.flow, s.num)).cloned().unwrap_or(0))
+                               .collect::<Vec<usize>>();
+    let mut histogram = vec![0; counts.iter().cloned().max().unwrap_or(0) + 1];
+    for &c in &counts {
+        histogram[c] += 1;
+    }
+
+    let mut reasons = [0; 4];
+    for (report, _) in study.image_reports()? {
+        for (n, &reason) in reasons.iter_mut().zip(&[report.dark, report.bright, report.blurry, report.grainy]) {
+            *n += reason as u32;
+        }
+    }
+
+    let scale = (1..6).map(|v| v.to_string()).collect::<Vec<_>>();
+    Ok(Dashboard {
+        trials,
+        raters: raters.len(),
+        active_day: active_day.len(),
+        active_week: active_week.len(),
+        surfaces: counts.len(),
+        unrated: histogram[0],
+        ratings_per_day: charts::bar_chart(&day_labels, &days.values().map(|d| d.0 as f64).collect::<Vec<_>>(), "Ratings"),
+        raters_per_day: charts::bar_chart(&day_labels, &days.values().map(|d| d.1.len() as f64).collect::<Vec<_>>(), "Raters"),
+        coverage: charts::bar_chart(&(0..histogram.len()).map(|c| c.to_string()).collect::<Vec<_>>(),
+                                    &histogram.iter().map(|&n| n as f64).collect::<Vec<_>>(),
+                                    "Surfaces"),
+        distributions: questions.iter()
+                                .zip(answers)
+                                .map(|(q, a)| Distribution {
+                                    question: q.short,
+                                    chart: charts::bar_chart(&scale, &a.iter().map(|&n| n as f64).collect::<Vec<_>>(), "Answers"),
+                                })
+                                .collect(),
+        reports: charts::bar_chart(&["dark".to_owned(), "bright".to_owned(), "blurry".to_owned(), "grainy".to_owned()],
+                                   &reasons.iter().map(|&n| n as f64).collect::<Vec<_>>(),
+                                   "Reports"),
+    })
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use csv;
+
+use analysis::{self, Moments};
+use errors::*;
+use settings;
+use study::Study;
+
+/// Limits beyond which a rater is flagged as careless
+pub struct Thresholds {
+    /// Raters with fewer rating trials than this are not flagged (their statistics are too noisy)
+    pub min_trials: usize,
+    /// Flag raters whose mean per-dimension response variance is below this
+    pub min_variance: f64,
+    /// Flag raters who gave their most common answer vector in more than this fraction of trials
+    pub max_identical: f64,
+    /// Flag raters whose median time between trials is below this (seconds)
+    pub min_latency: f64,
+    /// Flag raters whose correlation with the consensus of the other raters is below this
+    pub min_consensus: f64,
+    /// Flag raters who pass less than this fraction of gold-standard checks
+    pub min_gold: f64,
+}
+
+/// Quality diagnostics of one rater in one study
+#[derive(Serialize)]
+pub struct RaterDiagnostics {
+    /// Participant ID
+    pub id: String,
+    /// Number of Likert ratings
+    pub trials: usize,
+    /// Variance of the rater's answers, averaged over dimensions
+    pub variance: f64,
+    /// Fraction of trials on which the rater gave their most common answer vector
+    pub identical: f64,
+    /// Median time between consecutive trials (seconds), if the trial log has any
+    pub latency: Option<f64>,
+    /// Correlation of the rater's answers with the mean of the other raters on the same surfaces
+    pub consensus: f64,
+    /// Number of gold-standard checks
+    pub gold_checks: u32,
+    /// Fraction of gold-standard checks passed
+    pub gold: Option<f64>,
+    /// Thresholds the rater fails
+    pub flags: Vec<&'static str>,
+}
+
+impl RaterDiagnostics {
+    fn flag(&mut self, thresholds: &Thresholds) {
+        if self.trials < thresholds.min_trials {
+            return;
+        }
+        if self.variance < thresholds.min_variance {
+            self.flags.push("variance");
+        }
+        if self.identical > thresholds.max_identical {
+            self.flags.push("identical");
+        }
+        if self.latency.map_or(false, |l| l < thresholds.min_latency) {
+            self.flags.push("latency");
+        }
+        if self.consensus < thresholds.min_consensus {
+            self.flags.push("consensus");
+        }
+        if self.gold.map_or(false, |g| g < thresholds.min_gold) {
+            self.flags.push("gold");
+        }
+    }
+}
+
+/// Diagnostics of every rater who submitted Likert ratings in a study (whether or not they are
+/// excluded already), sorted by participant ID
+pub fn diagnostics(study: &Study) -> Result<Vec<RaterDiagnostics>> {
+    let questions = study.config.questionnaire;
+    let ratings = study.ratings()?;
+
+    // sum and count of all answers per surface and dimension, for the leave-one-out consensus
+    let mut totals = HashMap::new();
+    let mut vectors = BTreeMap::<&str, Vec<Vec<Option<u8>>>>::new();
+    for &(ref surface, ref user) in &ratings {
+        let answers = questions.iter()
+                               .map(|q| surface.ratings.get(q.short).map(|a| a.0))
+                               .collect::<Vec<_>>();
+        for (d, a) in answers.iter().enumerate() {
+            if let Some(a) = *a {
+                let total = totals.entry((surface.date, surface.flow, surface.num, d)).or_insert((0.0, 0));
+                total.0 += a as f64;
+                total.1 += 1;
+            }
+        }
+        vectors.entry(&user[..]).or_insert_with(Vec::new).push(answers);
+    }
+
+    let mut consensus = HashMap::<&str, Vec<(f64, f64)>>::new();
+    for &(ref surface, ref user) in &ratings {
+        for (d, q) in questions.iter().enumerate() {
+            if let Some(a) = surface.ratings.get(q.short) {
+                let (sum, n) = totals[&(surface.date, surface.flow, surface.num, d)];
+                if n > 1 {
+                    let x = a.0 as f64;
+                    consensus.entry(&user[..]).or_insert_with(Vec::new).push((x, (sum - x) / (n - 1) as f64));
+                }
+            }
+        }
+    }
+
+    // only main-phase ratings count towards speeding (practice, gold checks and reports are paced differently)
+    let mut times = HashMap::<String, Vec<u64>>::new();
+    for row in study.trials()? {
+        if row.response == "rating" {
+            times.entry(row.user).or_insert_with(Vec::new).push(row.time);
+        }
+    }
+
+    let mut gold = HashMap::<String, (u32, u32)>::new();
+    for row in study.checks()? {
+        if row.kind == "gold" {
+            let g = gold.entry(row.user).or_insert((0, 0));
+            g.0 += row.passed as u32;
+            g.1 += 1;
+        }
+    }
+
+    let mut results = vec![];
+    for (&user, answers) in &vectors {
+        let mut moments = vec![Moments::default(); questions.len()];
+        let mut counts = HashMap::new();
+        for vector in answers {
+            for (m, a) in moments.iter_mut().zip(vector) {
+                if let Some(a) = *a {
+                    m.push(a as f64);
+                }
+            }
+            *counts.entry(vector).or_insert(0) += 1;
+        }
+
+        let latency = times.get(user).and_then(|t| {
+            let mut t = t.clone();
+            t.sort();
+            let gaps = t.windows(2).map(|w| (w[1] - w[0]) as f64).collect::<Vec<_>>();
+            if gaps.is_empty() { None } else { Some(analysis::median(&gaps)) }
+        });
+
+        let mut diagnostics = RaterDiagnostics {
+            id: user.to_owned(),
+            trials: answers.len(),
+            variance: moments.iter().map(|m| m.variance()).sum::<f64>() / questions.len() as f64,
+            identical: *counts.values().max().unwrap() as f64 / answers.len() as f64,
+            latency,
+            consensus: consensus.get(user).map_or(::std::f64::NAN, |pairs| analysis::pearson(pairs)),
+            gold_checks: gold.get(user).map_or(0, |g| g.1),
+            gold: gold.get(user).map(|&(passed, total)| passed as f64 / total as f64),
+            flags: vec![],
+        };
+        diagnostics.flag(&settings::DIAGNOSTICS);
+        results.push(diagnostics);
+    }
+    Ok(results)
+}
+
+/// IDs of the raters flagged by the diagnostics
+pub fn flagged(study: &Study) -> Result<HashSet<String>> {
+    Ok(diagnostics(study)?.into_iter()
+                          .filter(|d| !d.flags.is_empty())
+                          .map(|d| d.id)
+                          .collect())
+}
+
+/// Exclusion list of the raters flagged by the diagnostics (a CSV file with a "User" column)
+pub fn exclusion_list(study: &Study) -> Result<String> {
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        csv.write_record(&["User", "Flags"])?;
+        for d in diagnostics(study)? {
+            if !d.flags.is_empty() {
+                csv.write_record(&[d.id, d.flags.join(" ")])?;
+            }
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Save the diagnostics' exclusion list as the study's own, so that every export leaves the flagged raters out
+///
+/// Returns the number of raters on the list.
+pub fn save_exclusion_list(study: &Study) -> Result<usize> {
+    let list = exclusion_list(study)?;
+    let path = study.output(settings::RATER_EXCLUSIONS);
+    File::create(&path)?.write_all(list.as_bytes())?;
+
+    let ids = read_exclusion_list(&path)?;
+    for (id, info) in study.users.lock().unwrap().iter_mut() {
+        info.listed = ids.contains(id);
+    }
+    Ok(ids.len())
+}
+
+/// IDs on an exclusion list (a CSV file with a "User" column)
+fn read_exclusion_list<P: AsRef<Path>>(path: P) -> Result<HashSet<String>> {
+    let mut csv = csv::Reader::from_reader(File::open(path.as_ref())?);
+    let column = csv.headers()?.iter()
+                               .position(|h| h == "User")
+                               .ok_or_else(|| ErrorKind::Parse(path.as_ref().to_owned()))?;
+    let mut ids = HashSet::new();
+    for row in csv.records() {
+        ids.insert(row?[column].to_owned());
+    }
+    Ok(ids)
+}
+
+/// Read an exclusion list and mark the listed raters as excluded in every study
+pub fn apply_exclusion_list<P: AsRef<Path>>(path: P, studies: &[Study]) -> Result<usize> {
+    let ids = read_exclusion_list(path)?;
+    for study in studies {
+        for (id, info) in study.users.lock().unwrap().iter_mut() {
+            if ids.contains(id) {
+                info.listed = true;
+            }
+        }
+    }
+    Ok(ids.len())
+}
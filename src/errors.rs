@@ -1,8 +1,13 @@
+use std::fmt::Debug;
 use std::io;
 use std::path::PathBuf;
 
+use rand::{self, Rng};
 use rocket::error::LaunchError;
-use rocket::response::Failure;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Response, Responder};
+use rocket_contrib::{Json, Template};
 use glob::{GlobError, PatternError};
 
 pub use std::result::Result as StdResult;
@@ -12,7 +17,9 @@ error_chain! {
         IoOp(ioerr: io::Error, op: &'static str, path: PathBuf) {}
         Parse(p: PathBuf) {}
         BadParam(msg: &'static str) {}
-        Rocket(f: Failure) {}
+        UnknownStudy(id: String) {}
+        PendingStep(step: &'static str) {}
+        Responded(r: ErrorResponse) {}
     }
 
     foreign_links {
@@ -25,9 +32,97 @@ error_chain! {
 }
 
 // HACK: this makes it possible to invoke one handler from another
-impl From<Failure> for Error {
-    fn from(f: Failure) -> Self {
-        Error::from_kind(ErrorKind::Rocket(f))
+impl From<ErrorResponse> for Error {
+    fn from(r: ErrorResponse) -> Self {
+        Error::from_kind(ErrorKind::Responded(r))
     }
 }
 
+impl Error {
+    /// HTTP status to respond with when a handler fails with this error
+    pub fn status(&self) -> Status {
+        match *self.kind() {
+            ErrorKind::Io(ref ioerr) | ErrorKind::IoOp(ref ioerr, ..) => match ioerr.kind() {
+                io::ErrorKind::NotFound => Status::NotFound,
+                io::ErrorKind::PermissionDenied => Status::Forbidden,
+                _ => Status::InternalServerError
+            },
+            ErrorKind::BadParam { .. } => Status::BadRequest,
+            ErrorKind::UnknownStudy { .. } => Status::NotFound,
+            ErrorKind::PendingStep { .. } => Status::Forbidden,
+            ErrorKind::Responded(ref r) => r.status,
+            _ => Status::InternalServerError
+        }
+    }
+
+    /// Explanation that is safe to show to clients (the details of internal errors only go to the log)
+    pub fn public_message(&self) -> String {
+        match *self.kind() {
+            ErrorKind::BadParam(msg) => msg.to_owned(),
+            ErrorKind::UnknownStudy(ref id) => format!("There is no study called \"{}\".", id),
+            ErrorKind::PendingStep(step) => format!("Please complete the {} step first.", step),
+            ErrorKind::Responded(ref r) => r.message.clone(),
+            _ => explanation(self.status()).to_owned()
+        }
+    }
+}
+
+/// Generic explanation of an error status for participants
+pub fn explanation(status: Status) -> &'static str {
+    match status.code {
+        400 | 422 => "Something was wrong with the request (perhaps a form was incomplete).",
+        401 => "You need to log in first.",
+        403 => "You are not allowed to see this page.",
+        404 => "The page you were looking for does not exist.",
+        _ => "Something went wrong on our side."
+    }
+}
+
+/// Error response with an ID that is also written to the server log
+///
+/// Rendered with the error template, or as a JSON body for requests to the API.
+#[derive(Debug)]
+pub struct ErrorResponse {
+    /// HTTP status
+    pub status: Status,
+    /// Random ID identifying the log line
+    pub id: String,
+    /// Explanation for the user
+    pub message: String,
+}
+
+impl ErrorResponse {
+    /// Log the cause of an error under a fresh ID
+    pub fn log<D: Debug>(status: Status, message: String, cause: D) -> ErrorResponse {
+        let id = format!("{:08x}", rand::thread_rng().gen::<u32>());
+        println!("ERROR {} ({}): {:?}", id, status, cause);
+        ErrorResponse { status, id, message }
+    }
+}
+
+impl From<Error> for ErrorResponse {
+    fn from(err: Error) -> ErrorResponse {
+        match err {
+            // already logged by the handler that was invoked
+            Error(ErrorKind::Responded(r), _) => r,
+            err => ErrorResponse::log(err.status(), err.public_message(), err)
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ErrorResponse {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let body = json!({
+            "status": self.status.code,
+            "reason": self.status.reason,
+            "message": self.message,
+            "id": self.id
+        });
+        let response = if req.uri().path().starts_with("/api/") {
+            Json(json!({ "error": body })).respond_to(req)?
+        } else {
+            Template::render("error", body).respond_to(req)?
+        };
+        Response::build_from(response).status(self.status).ok()
+    }
+}
@@ -0,0 +1,595 @@
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use csv;
+use serde_json;
+
+use agreement;
+use analysis::{self, Estimate, Moments};
+use diagnostics;
+use errors::*;
+use reliability;
+use structs::*;
+use study::{Study, Mode};
+use utils::*;
+
+/// Mean Likert rating of each surface and question, over all Likert-mode studies
+fn likert_means(studies: &[Study]) -> Result<HashMap<((Datestamp, FlowType, u32), String), f64>> {
+    let mut sums = HashMap::new();
+    for study in studies {
+        if let Mode::Likert = study.config.mode {
+            let excluded = study.excluded_users();
+            for (surface, user) in study.ratings()? {
+                if excluded.contains(&user) {
+                    continue;
+                }
+                for (question, answer) in surface.ratings {
+                    let sum = sums.entry(((surface.date, surface.flow, surface.num), question)).or_insert((0.0, 0));
+                    sum.0 += answer.0 as f64;
+                    sum.1 += 1;
+                }
+            }
+        }
+    }
+    Ok(sums.into_iter().map(|(k, (sum, n))| (k, sum / n as f64)).collect())
+}
+
+/// Bradley-Terry scores fitted to a study's pairwise comparisons (including those implied by
+/// rankings), next to the mean Likert ratings
+pub fn bradley_terry(study: &Study, studies: &[Study]) -> Result<String> {
+    let questions = study.config.questionnaire;
+    let excluded = study.excluded_users();
+
+    let mut outcomes = questions.iter().map(|_| vec![]).collect::<Vec<_>>();
+    // distinct (user, pair) comparisons, whichever questions they were asked about
+    let mut compared = HashSet::new();
+    for row in study.pairs()?.into_iter().chain(study.implied_pairs()?) {
+        if excluded.contains(&row.user) {
+            continue;
+        }
+        let (winner, loser) = row.outcome();
+        let q = questions.iter().position(|q| q.short == row.question);
+        if let (Some(q), Some(w), Some(l)) = (q, study.index_of(winner), study.index_of(loser)) {
+            outcomes[q].push((w, l));
+            compared.insert((row.user.clone(), cmp::min(w, l), cmp::max(w, l)));
+        }
+    }
+    let mut counts = vec![0; study.surfaces.len()];
+    for &(_, a, b) in &compared {
+        counts[a] += 1;
+        counts[b] += 1;
+    }
+    let scores = outcomes.iter()
+                         .map(|o| analysis::bradley_terry(study.surfaces.len(), o))
+                         .collect::<Vec<_>>();
+    let means = likert_means(studies)?;
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        let mut headers = vec!["Date".to_owned(), "Flow type".to_owned(), "Number".to_owned(), "Comparisons".to_owned()];
+        for q in questions {
+            headers.push(format!("{} BT", capitalize(q.short)));
+            headers.push(format!("{} mean", capitalize(q.short)));
+        }
+        csv.write_record(&headers)?;
+
+        for (i, surf) in study.surfaces.iter().enumerate() {
+            let key = (surf.date, surf.flow, surf.num);
+            let mut row = vec![surf.date.to_string(), surf.flow.to_string(), surf.num.to_string(), counts[i].to_string()];
+            for (q, question) in questions.iter().enumerate() {
+                row.push(scores[q][i].to_string());
+                row.push(means.get(&(key, question.short.to_owned())).map(|m| m.to_string()).unwrap_or_default());
+            }
+            csv.write_record(&row)?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Pairwise comparisons implied by a study's rankings, in the same layout as the comparisons file
+pub fn implied_pairs(study: &Study) -> Result<String> {
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        csv.write_record(&["User", "Date A", "Flow type A", "Number A", "Date B", "Flow type B", "Number B", "Question", "Winner"])?;
+        for p in study.implied_pairs()? {
+            csv.write_record(&[p.user, p.a_date.to_string(), p.a_flow.to_string(), p.a_num.to_string(),
+                               p.b_date.to_string(), p.b_flow.to_string(), p.b_num.to_string(), p.question, p.winner])?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Agreement statistics for each category of a labeling study
+pub fn label_agreement(study: &Study) -> Result<String> {
+    let categories = match study.config.mode {
+        Mode::Labeling { categories, .. } => categories,
+        _ => Err(ErrorKind::BadParam("study does not collect labels"))?
+    };
+    let excluded = study.excluded_users();
+
+    let mut counts = HashMap::<(Datestamp, FlowType, u32), Vec<u32>>::new();
+    let mut unknown = 0;
+    for row in study.labels()? {
+        if excluded.contains(&row.user) {
+            continue;
+        }
+        if let Some(j) = categories.iter().position(|&c| c == row.category) {
+            counts.entry((row.date, row.flow, row.num)).or_insert_with(|| vec![0; categories.len()])[j] += 1;
+        } else if row.category == DONT_KNOW {
+            unknown += 1;
+        }
+    }
+    let counts = counts.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+    let (kappa, stats) = analysis::fleiss_kappa(&counts, categories.len());
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        csv.write_record(&["Category", "Labels", "Majority", "Specific agreement", "Kappa"])?;
+        for (category, stat) in categories.iter().zip(stats) {
+            csv.write_record(&[category.to_string(), stat.labels.to_string(), stat.majority.to_string(),
+                               stat.specific_agreement.map_or(String::new(), |a| a.to_string()),
+                               stat.kappa.map_or(String::new(), |k| k.to_string())])?;
+        }
+        csv.write_record(&[DONT_KNOW.to_owned(), unknown.to_string(), String::new(), String::new(), String::new()])?;
+        csv.write_record(&["All".to_owned(), String::new(), String::new(), String::new(),
+                           if kappa.is_finite() { kappa.to_string() } else { String::new() }])?;
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Inter-rater reliability of every Likert study, by dimension and end-effector type
+pub fn reliability(studies: &[Study]) -> Result<String> {
+    fn estimate(row: &mut Vec<String>, e: &Estimate) {
+        row.push(e.value.to_string());
+        row.push(e.low.to_string());
+        row.push(e.high.to_string());
+    }
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        csv.write_record(&["Study", "Flow type", "Question", "Surfaces", "Ratings", "Raters",
+                           "Alpha", "Alpha low", "Alpha high",
+                           "ICC", "ICC low", "ICC high", "ICC raters", "ICC surfaces",
+                           "Spearman", "Spearman low", "Spearman high", "Spearman pairs"])?;
+        for r in reliability::reliability(studies)? {
+            let mut row = vec![r.study.to_owned(), r.flow.map_or("all".to_owned(), |f| f.to_string()), r.question.to_owned(),
+                               r.surfaces.to_string(), r.ratings.to_string(), r.raters.to_string()];
+            estimate(&mut row, &r.alpha);
+            estimate(&mut row, &r.icc);
+            row.push(r.icc_raters.to_string());
+            row.push(r.icc_surfaces.to_string());
+            estimate(&mut row, &r.spearman);
+            row.push(r.spearman_pairs.to_string());
+            csv.write_record(&row)?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Agreement between experimenter and crowd for every Likert study, by dimension and end-effector type
+pub fn agreement(studies: &[Study]) -> Result<String> {
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        let mut headers = vec!["Study".to_owned(), "Flow type".to_owned(), "Question".to_owned(), "Surfaces".to_owned(),
+                               "Pearson".to_owned(), "Spearman".to_owned(), "Mean absolute difference".to_owned(),
+                               "Bias".to_owned(), "SD".to_owned(), "Lower limit".to_owned(), "Upper limit".to_owned()];
+        for e in 1..6 {
+            for c in 1..6 {
+                headers.push(format!("Experimenter {} crowd {}", e, c));
+            }
+        }
+        csv.write_record(&headers)?;
+
+        for a in agreement::agreement(studies)? {
+            let mut row = vec![a.study.to_owned(), a.flow.map_or("all".to_owned(), |f| f.to_string()), a.question.to_owned(),
+                               a.surfaces.to_string(), a.pearson.to_string(), a.spearman.to_string(), a.mad.to_string(),
+                               a.bland_altman.bias.to_string(), a.bland_altman.sd.to_string(),
+                               a.bland_altman.lower.to_string(), a.bland_altman.upper.to_string()];
+            row.extend(a.confusion.iter().flat_map(|r| r.iter()).map(|n| n.to_string()));
+            csv.write_record(&row)?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Experimenter rating next to the crowd mean for every surface and dimension of a Likert study
+pub fn surface_agreement(study: &Study) -> Result<String> {
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        csv.write_record(&["Date", "Flow type", "Number", "Question", "Experimenter", "Crowd mean", "Ratings", "Difference"])?;
+        for r in agreement::surfaces(study)? {
+            csv.write_record(&[r.date.to_string(), r.flow.to_string(), r.num.to_string(), r.question.to_owned(),
+                               r.experimenter.to_string(), r.crowd.to_string(), r.ratings.to_string(), r.difference().to_string()])?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Summary of one dimension's ratings of one surface
+#[derive(Serialize)]
+pub struct RatingSummary {
+    /// Mean rating (or z-score)
+    pub mean: f64,
+    /// Median rating (or z-score)
+    pub median: f64,
+    /// Sample standard deviation
+    pub sd: f64,
+    /// Number of ratings
+    pub count: usize,
+}
+
+/// Number of times a surface was reported, in total and for each reason
+#[derive(Serialize, Default)]
+pub struct ReportCounts {
+    /// Number of reports
+    pub total: u32,
+    /// Reports for each reason (a report may give several)
+    pub dark: u32,
+    pub bright: u32,
+    pub blurry: u32,
+    pub grainy: u32,
+}
+
+/// One scanned surface with its aggregated ratings, for the machine learning pipeline
+#[derive(Serialize)]
+pub struct DatasetRow {
+    /// Episode date
+    pub date: Datestamp,
+    /// Episode flow type
+    pub flow: FlowType,
+    /// Episode number
+    #[serde(rename="number")]
+    pub num: u32,
+    /// Experimenter's rating of each dimension (from the flow file)
+    pub experimenter: BTreeMap<&'static str, Option<u8>>,
+    /// Participants' ratings of each dimension
+    pub ratings: BTreeMap<&'static str, RatingSummary>,
+    /// Image quality reports
+    pub reports: ReportCounts,
+}
+
+/// Summary statistics of a sample (undefined ones are NaN)
+fn summarize(xs: &[f64]) -> RatingSummary {
+    let mut moments = Moments::default();
+    for &x in xs {
+        moments.push(x);
+    }
+    RatingSummary {
+        mean: if xs.is_empty() { ::std::f64::NAN } else { moments.mean },
+        median: analysis::median(xs),
+        sd: if xs.len() < 2 { ::std::f64::NAN } else { moments.variance().sqrt() },
+        count: xs.len(),
+    }
+}
+
+/// Join a study's surface index, ratings and reports into one row per surface
+pub fn dataset(study: &Study, options: &DatasetOptions) -> Result<Vec<DatasetRow>> {
+    let questions = study.config.questionnaire;
+    let mut excluded = if options.include_excluded { HashSet::new() } else { study.excluded_users() };
+    if options.exclude_flagged {
+        excluded.extend(diagnostics::flagged(study)?);
+    }
+    let ratings = study.ratings()?
+                       .into_iter()
+                       .filter(|&(_, ref user)| !excluded.contains(user))
+                       .collect::<Vec<_>>();
+
+    // each rater's mean and SD on each dimension, for z-scores
+    let mut raters = HashMap::<&str, Vec<Moments>>::new();
+    if options.normalize {
+        for &(ref surface, ref user) in &ratings {
+            let moments = raters.entry(&user[..]).or_insert_with(|| vec![Moments::default(); questions.len()]);
+            for (m, q) in moments.iter_mut().zip(questions) {
+                if let Some(a) = surface.ratings.get(q.short) {
+                    m.push(a.0 as f64);
+                }
+            }
+        }
+    }
+
+    let mut values = HashMap::<(Datestamp, FlowType, u32), Vec<Vec<f64>>>::new();
+    for &(ref surface, ref user) in &ratings {
+        let v = values.entry((surface.date, surface.flow, surface.num))
+                      .or_insert_with(|| vec![vec![]; questions.len()]);
+        for (i, q) in questions.iter().enumerate() {
+            if let Some(a) = surface.ratings.get(q.short) {
+                let x = a.0 as f64;
+                v[i].push(match raters.get(&user[..]) {
+                    Some(m) => {
+                        // a rater who always gives the same answer carries no information beyond the mean
+                        let sd = m[i].variance().sqrt();
+                        if sd > 0.0 && sd.is_finite() { (x - m[i].mean) / sd } else { 0.0 }
+                    }
+                    None => x
+                });
+            }
+        }
+    }
+
+    let mut reports = HashMap::<(Datestamp, FlowType, u32), ReportCounts>::new();
+    for (report, user) in study.image_reports()? {
+        if excluded.contains(&user) {
+            continue;
+        }
+        let counts = reports.entry((report.date, report.flow, report.num)).or_insert_with(Default::default);
+        counts.total += 1;
+        counts.dark += report.dark as u32;
+        counts.bright += report.bright as u32;
+        counts.blurry += report.blurry as u32;
+        counts.grainy += report.grainy as u32;
+    }
+
+    let mut rows = vec![];
+    for surface in &study.surfaces {
+        let key = (surface.date, surface.flow, surface.num);
+        let empty = vec![vec![]; questions.len()];
+        let v = values.get(&key).unwrap_or(&empty);
+        let count = v.iter().map(|d| d.len()).max().unwrap_or(0);
+        if count < options.min_count.unwrap_or(0) as usize {
+            continue;
+        }
+
+        rows.push(DatasetRow {
+            date: surface.date,
+            flow: surface.flow,
+            num: surface.num,
+            experimenter: questions.iter().map(|q| (q.short, surface.ratings.get(q.short).map(|a| a.0))).collect(),
+            ratings: questions.iter().zip(v).map(|(q, xs)| (q.short, summarize(xs))).collect(),
+            reports: reports.remove(&key).unwrap_or_default(),
+        });
+    }
+    Ok(rows)
+}
+
+/// Per-surface dataset as CSV (one column per statistic and dimension)
+pub fn dataset_csv(study: &Study, options: &DatasetOptions) -> Result<String> {
+    let questions = study.config.questionnaire;
+    let number = |x: f64| if x.is_nan() { String::new() } else { x.to_string() };
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        let mut headers = vec!["Date".to_owned(), "Flow type".to_owned(), "Number".to_owned()];
+        for q in questions {
+            let q = capitalize(q.short);
+            for column in &["experimenter", "mean", "median", "SD", "count"] {
+                headers.push(format!("{} {}", q, column));
+            }
+        }
+        for column in &["Reports", "Dark", "Bright", "Blurry", "Grainy"] {
+            headers.push(column.to_string());
+        }
+        csv.write_record(&headers)?;
+
+        for row in dataset(study, options)? {
+            let mut record = vec![row.date.to_string(), row.flow.to_string(), row.num.to_string()];
+            for q in questions {
+                let summary = &row.ratings[q.short];
+                record.push(row.experimenter[q.short].map(|e| e.to_string()).unwrap_or_default());
+                record.push(number(summary.mean));
+                record.push(number(summary.median));
+                record.push(number(summary.sd));
+                record.push(summary.count.to_string());
+            }
+            let r = &row.reports;
+            for n in &[r.total, r.dark, r.bright, r.blurry, r.grainy] {
+                record.push(n.to_string());
+            }
+            csv.write_record(&record)?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Per-surface dataset as JSON Lines (one object per surface)
+pub fn dataset_jsonl(study: &Study, options: &DatasetOptions) -> Result<String> {
+    let mut out = String::new();
+    for row in dataset(study, options)? {
+        out.push_str(&serde_json::to_string(&row).unwrap());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Questionnaire dimensions as data dictionary entries (scale and anchors)
+fn dimensions(study: &Study) -> Vec<serde_json::Value> {
+    study.config.questionnaire.iter()
+         .map(|q| json!({
+             "name": q.short,
+             "prompt": q.prompt,
+             "scale": { "min": 1, "max": 5, "type": "ordinal" },
+             "anchors": { "1": q.low, "5": q.high }
+         }))
+         .collect()
+}
+
+/// Participant conditions and exclusion set, for the tidy exports
+fn participants(study: &Study) -> (HashMap<String, String>, HashSet<String>) {
+    let users = study.users.lock().unwrap();
+    let conditions = users.iter()
+                          .filter_map(|(id, info)| study.condition(info).map(|c| (id.clone(), c.name.to_owned())))
+                          .collect();
+    let excluded = users.iter()
+                        .filter(|&(_, info)| info.excluded())
+                        .map(|(id, _)| id.clone())
+                        .collect();
+    (conditions, excluded)
+}
+
+/// Ratings and reports in long format: one row per participant, surface and dimension
+pub fn long(study: &Study) -> Result<String> {
+    let (conditions, excluded) = participants(study);
+    let experimenter = study.surfaces.iter()
+                                     .map(|s| ((s.date, s.flow, s.num), &s.ratings))
+                                     .collect::<HashMap<_, _>>();
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        let mut headers = vec!["User", "Date", "Flow type", "Number", "Kind", "Dimension", "Value", "Experimenter"];
+        if !study.config.conditions.is_empty() {
+            headers.insert(1, "Condition");
+        }
+        csv.write_record(&headers)?;
+
+        {
+            let mut write = |user: &str, date: Datestamp, flow: FlowType, num: u32, kind: &str, dimension: &str, value: String| -> Result<()> {
+                let expert = if kind == "rating" {
+                    experimenter.get(&(date, flow, num))
+                                .and_then(|r| r.get(dimension))
+                                .map(|r| r.0.to_string())
+                                .unwrap_or_default()
+                } else {
+                    String::new()
+                };
+                let mut row = vec![user.to_owned(), date.to_string(), flow.to_string(), num.to_string(),
+                                   kind.to_owned(), dimension.to_owned(), value, expert];
+                if !study.config.conditions.is_empty() {
+                    row.insert(1, conditions.get(user).cloned().unwrap_or_default());
+                }
+                csv.write_record(&row)?;
+                Ok(())
+            };
+
+            for (surface, user) in study.ratings()? {
+                if excluded.contains(&user) {
+                    continue;
+                }
+                for q in study.config.questionnaire {
+                    if let Some(a) = surface.ratings.get(q.short) {
+                        write(&user, surface.date, surface.flow, surface.num, "rating", q.short, a.0.to_string())?;
+                    }
+                }
+            }
+            for (report, user) in study.image_reports()? {
+                if excluded.contains(&user) {
+                    continue;
+                }
+                for &(reason, value) in &[("dark", report.dark), ("bright", report.bright), ("blurry", report.blurry), ("grainy", report.grainy)] {
+                    write(&user, report.date, report.flow, report.num, "report", reason, (value as u8).to_string())?;
+                }
+            }
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Data dictionary of the long-format export
+pub fn long_dictionary(study: &Study) -> String {
+    let mut columns = vec![
+        json!({ "name": "User", "type": "string", "description": "Participant ID" }),
+        json!({ "name": "Date", "type": "integer", "description": "Episode date (YYYYMMDD)" }),
+        json!({ "name": "Flow type", "type": "string", "description": "End-effector type",
+                "values": ["stickcam", "optocam", "biocam"] }),
+        json!({ "name": "Number", "type": "integer", "description": "Episode number within the date and flow type" }),
+        json!({ "name": "Kind", "type": "string", "description": "Whether the row is a rating or an image quality report",
+                "values": ["rating", "report"] }),
+        json!({ "name": "Dimension", "type": "string",
+                "description": "Questionnaire dimension (for ratings, see \"dimensions\") or report reason (for reports)",
+                "values": study.config.questionnaire.iter().map(|q| q.short)
+                               .chain(vec!["dark", "bright", "blurry", "grainy"])
+                               .collect::<Vec<_>>() }),
+        json!({ "name": "Value", "type": "integer",
+                "description": "Rating on the dimension's scale, or 1 if the report gave the reason and 0 if not" }),
+        json!({ "name": "Experimenter", "type": "integer", "nullable": true,
+                "description": "Experimenter's rating of the surface on the dimension (ratings only, from the flow file)" }),
+    ];
+    if !study.config.conditions.is_empty() {
+        columns.insert(1, json!({ "name": "Condition", "type": "string", "description": "Between-subject condition",
+                                  "values": study.config.conditions.iter().map(|c| c.name).collect::<Vec<_>>() }));
+    }
+
+    serde_json::to_string_pretty(&json!({
+        "study": study.config.id,
+        "title": study.config.title,
+        "file": "long.csv",
+        "description": "One row per participant, surface and dimension. Participants excluded from the analysis are left out.",
+        "columns": columns,
+        "dimensions": dimensions(study)
+    })).unwrap()
+}
+
+/// Ratings in wide format: one row per surface and dimension, one column per participant
+///
+/// A participant who rated a surface more than once gets the mean of their ratings, and the
+/// "Repeated" column counts such participants in each row.
+pub fn wide(study: &Study) -> Result<String> {
+    let (_, excluded) = participants(study);
+    let questions = study.config.questionnaire;
+
+    let mut raters = BTreeSet::new();
+    // sum and number of each participant's ratings
+    let mut cells = BTreeMap::<((Datestamp, FlowType, u32), usize), HashMap<String, (u32, u32)>>::new();
+    for (surface, user) in study.ratings()? {
+        if excluded.contains(&user) {
+            continue;
+        }
+        raters.insert(user.clone());
+        for (d, q) in questions.iter().enumerate() {
+            if let Some(a) = surface.ratings.get(q.short) {
+                let cell = cells.entry(((surface.date, surface.flow, surface.num), d))
+                                .or_insert_with(HashMap::new)
+                                .entry(user.clone())
+                                .or_insert((0, 0));
+                cell.0 += a.0 as u32;
+                cell.1 += 1;
+            }
+        }
+    }
+
+    let mut buf = vec![];
+    {
+        let mut csv = csv::Writer::from_writer(&mut buf);
+        let mut headers = vec!["Date".to_owned(), "Flow type".to_owned(), "Number".to_owned(), "Dimension".to_owned(),
+                               "Repeated".to_owned()];
+        headers.extend(raters.iter().cloned());
+        csv.write_record(&headers)?;
+
+        for (&((date, flow, num), d), ratings) in &cells {
+            let repeated = ratings.values().filter(|&&(_, n)| n > 1).count();
+            let mut row = vec![date.to_string(), flow.to_string(), num.to_string(), questions[d].short.to_owned(),
+                               repeated.to_string()];
+            row.extend(raters.iter().map(|r| ratings.get(r).map(|&(sum, n)| (sum as f64 / n as f64).to_string()).unwrap_or_default()));
+            csv.write_record(&row)?;
+        }
+        csv.flush()?;
+    }
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Data dictionary of the wide-format export
+pub fn wide_dictionary(study: &Study) -> String {
+    serde_json::to_string_pretty(&json!({
+        "study": study.config.id,
+        "title": study.config.title,
+        "file": "wide.csv",
+        "description": "One row per surface and dimension, one column per participant (empty if the participant did not rate the surface). \
+                        Participants excluded from the analysis are left out.",
+        "columns": [
+            { "name": "Date", "type": "integer", "description": "Episode date (YYYYMMDD)" },
+            { "name": "Flow type", "type": "string", "description": "End-effector type", "values": ["stickcam", "optocam", "biocam"] },
+            { "name": "Number", "type": "integer", "description": "Episode number within the date and flow type" },
+            { "name": "Dimension", "type": "string", "description": "Questionnaire dimension (see \"dimensions\")",
+              "values": study.config.questionnaire.iter().map(|q| q.short).collect::<Vec<_>>() },
+            { "name": "Repeated", "type": "integer",
+              "description": "Number of participants who rated the surface more than once (their column holds the mean)" },
+            { "pattern": "<participant ID>", "type": "number", "nullable": true,
+              "description": "The participant's rating of the surface on the dimension (the mean if they rated it more than once)" }
+        ],
+        "dimensions": dimensions(study)
+    })).unwrap()
+}
@@ -1,21 +1,8 @@
 macro_rules! handle {
     ($(#[$attr:meta])* $vis:vis fn $name:ident $params:tt -> $ret:ty { $($body:tt)* }) => {
         $(#[$attr])*
-        $vis fn $name $params -> StdResult<$ret, Failure> {
-            do catch { $($body)* }.map_err(|err: Error| {
-                println!("ERROR: {:?}", err);
-                let mut code = 500;
-                match *err.kind() {
-                    ErrorKind::Io(ref ioerr) | ErrorKind::IoOp(ref ioerr, ..) => match ioerr.kind() {
-                        io::ErrorKind::NotFound => code = 404,
-                        io::ErrorKind::PermissionDenied => code = 403,
-                        _ => {}
-                    },
-                    ErrorKind::BadParam { .. } => code = 400,
-                    _ => {}
-                }
-                Failure(Status::from_code(code).unwrap())
-            })
+        $vis fn $name $params -> StdResult<$ret, ErrorResponse> {
+            do catch { $($body)* }.map_err(|err: Error| ErrorResponse::from(err))
         }
     }
 }
@@ -35,6 +22,37 @@ macro_rules! handle_login {
     }
 }
 
+macro_rules! handle_admin {
+    (#[$method:ident($($route:tt)*)] $vis:vis fn $name:ident/$name_login:ident($admin:ident: $admin_ty:ty, $($params:tt)*) -> $ret:ty { $($body:tt)* }) => {
+        #[$method($($route)*, rank=1)]
+        #[allow(unused_variables)]
+        $vis fn $name_login(uri: &URI, $($params)*) -> Redirect {
+            Redirect::to(&format!("/admin/login?uri={}", URI::percent_encode(uri.as_str())))
+        }
+
+        handle! {
+            #[$method($($route)*)]
+            #[allow(unused_variables)]
+            $vis fn $name($admin: $admin_ty, $($params)*) -> $ret { $($body)* }
+        }
+    }
+}
+
+macro_rules! handle_api_login {
+    (#[$method:ident($($route:tt)*)] $vis:vis fn $name:ident/$name_login:ident($user:ident: $user_ty:ty, $($params:tt)*) -> $ret:ty { $($body:tt)* }) => {
+        #[$method($($route)*, rank=1)]
+        #[allow(unused_variables)]
+        $vis fn $name_login(uri: &URI, $($params)*) -> ErrorResponse {
+            ErrorResponse::log(Status::Unauthorized, explanation(Status::Unauthorized).to_owned(), format!("not logged in: {}", uri))
+        }
+
+        handle! {
+            #[$method($($route)*)]
+            $vis fn $name($user: $user_ty, $($params)*) -> $ret { $($body)* }
+        }
+    }
+}
+
 macro_rules! with_user {
     (
         $(#[$sattr:meta])*
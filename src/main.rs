@@ -18,80 +18,173 @@ extern crate csv;
 #[macro_use] extern crate unborrow;
 extern crate glob;
 extern crate rand;
+extern crate ring;
 extern crate flow;
 
 #[macro_use] mod macros;
+mod agreement;
+mod analysis;
+mod api;
+mod auth;
+mod catchers;
+mod charts;
+mod dashboard;
+mod diagnostics;
 mod errors;
+mod export;
+mod mock_platform;
+mod participants;
+mod reliability;
 mod routes;
+mod sampling;
 mod settings;
 mod structs;
+mod study;
 mod utils;
 
-use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs::{File, OpenOptions};
-use std::io::{Seek, SeekFrom};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::sync::Mutex;
 
 use rocket_contrib::Template;
 use glob::glob;
 
 // TODO remove globs
 use errors::*;
+use participants::Participants;
 use structs::*;
+use study::{Study, Studies};
 use utils::*;
 
 fn main() {
+    match env::args().nth(1).as_ref().map(|s| &s[..]) {
+        Some("hash-password") => {
+            hash_password().unwrap();
+            return;
+        }
+        Some("reliability") => {
+            export_reliability().unwrap();
+            return;
+        }
+        Some("dataset") => {
+            export_dataset().unwrap();
+            return;
+        }
+        Some("diagnostics") => {
+            export_diagnostics().unwrap();
+            return;
+        }
+        _ => {}
+    }
+
     try_main().unwrap();
     unreachable!();
 }
 
-fn try_main() -> Result<!> {
-    println!("Initializing output files...");
-    let mut users = HashMap::<User, UserInfo>::new();
-    let mut reports = HashSet::new();;
-    output_file(settings::RATINGS,
-                &["User", "Date", "Flow type", "Number", "Warm", "Hard", "Rough", "Sticky"],
-                |mut csv| {
-                    let ratings = csv.headers()?.iter()
-                                                .skip(4)
-                                                .map(|s| s.to_lowercase())
-                                                .collect::<Vec<_>>();
-                    for row in csv.records() {
-                        let mut row = row?;
-                        let answers = row.iter()
-                                         .skip(4)
-                                         .map(|s| s.parse())
-                                         .collect::<StdResult<Vec<_>,_>>()?;
-                        row.truncate(4);
-                        let row: SurfaceDataWithUser = row.deserialize(None)?;
-                        let (mut surface, username) = row.without_user();
-                        surface.ratings = ratings.iter()
-                                                 .cloned()
-                                                 .zip(answers)
-                                                 .collect();
-                        let user_info = users.entry(User { name: username }).or_insert_with(Default::default);
-                        user_info.seen.push((surface.date, surface.flow, surface.num));
-                    }
-                    Ok(())
-                })?;
-    output_file(settings::REPORTS,
-                &["User", "Date", "Flow type", "Number", "Dark", "Bright", "Blurry", "Grainy"],
-                |mut csv| {
-                    unborrow!(csv.set_headers(csv.headers().unwrap()
-                                                 .iter()
-                                                 .map(|s| s.split(' ').next().unwrap().to_lowercase())
-                                                 .collect()));
-                    for row in csv.deserialize() {
-                        let row: ReportWithUser = row?;
-                        let (report, username) = row.without_user();
-                        let user_info = users.entry(User { name: username }).or_insert_with(Default::default);
-                        user_info.seen.push((report.date, report.flow, report.num));
-                        reports.insert((report.date, report.flow, report.num));
-                    }
-                    Ok(())
-                })?;
+/// Read a password from stdin and print an `AdminAccount` entry for `settings::ADMINS`
+fn hash_password() -> Result<()> {
+    let name = env::args().nth(2).unwrap_or_else(|| "admin".into());
+    println!("Password for {}:", name);
+    let mut password = String::new();
+    io::stdin().read_line(&mut password)?;
+    let (salt, hash) = auth::hash_password(password.trim_right_matches(|c| c == '\r' || c == '\n'));
+    println!("AdminAccount {{ name: {:?}, salt: {:?}, hash: {:?} }},", name, salt, hash);
+    Ok(())
+}
+
+/// Take an `--exclude <file>` option out of the command line arguments
+fn exclusion_option(args: &mut Vec<String>) -> Result<Option<String>> {
+    match args.iter().position(|a| a == "--exclude") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(path))
+        }
+        Some(_) => bail!("--exclude needs an exclusion list"),
+        None => Ok(None)
+    }
+}
+
+/// Load the studies for a CLI export, additionally excluding the raters on an exclusion list
+fn load_excluding(exclude: Option<String>) -> Result<Studies> {
+    let (studies, _) = load()?;
+    if let Some(path) = exclude {
+        let n = diagnostics::apply_exclusion_list(&path, &studies)?;
+        println!("Excluding {} raters listed in {}", n, path);
+    }
+    Ok(studies)
+}
+
+/// Write the inter-rater reliability report to a CSV file
+///
+/// Usage: `reliability [<path>] [--exclude <exclusion list>]` (the path defaults to `reliability.csv`)
+fn export_reliability() -> Result<()> {
+    let mut args = env::args().skip(2).collect::<Vec<_>>();
+    let exclude = exclusion_option(&mut args)?;
+    let path = args.into_iter().next().unwrap_or_else(|| "reliability.csv".into());
+    let studies = load_excluding(exclude)?;
+    println!("Computing reliability...");
+    let mut file = File::create(&path)?;
+    file.write_all(export::reliability(&studies)?.as_bytes())?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+/// Write a study's per-surface dataset to a file
+///
+/// Usage: `dataset <study> <path> [--jsonl] [--normalize] [--include-excluded] [--exclude-flagged]
+/// [--min-count <n>] [--exclude <exclusion list>]`
+fn export_dataset() -> Result<()> {
+    let mut args = env::args().skip(2).collect::<Vec<_>>();
+    let exclude = exclusion_option(&mut args)?;
+    if args.len() < 2 {
+        bail!("usage: dataset <study> <path> [--jsonl] [--normalize] [--include-excluded] [--exclude-flagged] \
+               [--min-count <n>] [--exclude <exclusion list>]");
+    }
+    let mut options = DatasetOptions { format: "csv".into(), ..Default::default() };
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match &flag[..] {
+            "--jsonl" => options.format = "jsonl".into(),
+            "--normalize" => options.normalize = true,
+            "--include-excluded" => options.include_excluded = true,
+            "--exclude-flagged" => options.exclude_flagged = true,
+            "--min-count" => options.min_count = Some(flags.next().and_then(|n| n.parse().ok())
+                                                               .ok_or(ErrorKind::BadParam("--min-count needs a number"))?),
+            _ => bail!("unknown option {}", flag)
+        }
+    }
 
+    let studies = load_excluding(exclude)?;
+    let study = Study::find(&studies, &args[0])?;
+    let body = if options.format == "jsonl" {
+        export::dataset_jsonl(study, &options)?
+    } else {
+        export::dataset_csv(study, &options)?
+    };
+    File::create(&args[1])?.write_all(body.as_bytes())?;
+    println!("Wrote {}", args[1]);
+    Ok(())
+}
+
+/// Write the exclusion list suggested by a study's rater diagnostics
+///
+/// Usage: `diagnostics <study> <path>`
+fn export_diagnostics() -> Result<()> {
+    let args = env::args().skip(2).collect::<Vec<_>>();
+    if args.len() < 2 {
+        bail!("usage: diagnostics <study> <path>");
+    }
+    let (studies, _) = load()?;
+    let study = Study::find(&studies, &args[0])?;
+    File::create(&args[1])?.write_all(diagnostics::exclusion_list(study)?.as_bytes())?;
+    println!("Wrote {}", args[1]);
+    Ok(())
+}
+
+/// Scan the surfaces and restore the studies and participant registry from the output files
+fn load() -> Result<(Studies, Participants)> {
     println!("Scanning surfaces...");
     let mut surfaces = vec![];
     for dir in settings::DATADIRS {
@@ -103,26 +196,109 @@ fn try_main() -> Result<!> {
                                                            .with_extension("flow"))?);
         }
     }
+    surfaces.sort_by_key(|s| (s.date, s.flow, s.num));
+
+    let studies = settings::STUDIES.iter()
+                                   .map(|config| Study::load(config, &surfaces))
+                                   .collect::<Result<Studies>>()?;
+
+    println!("Loading participants...");
+    let participants = Participants::load()?;
+    participants.apply(&studies);
+
+    Ok((studies, participants))
+}
+
+/// Whether Rocket will find a secret key (in the environment or Rocket.toml) instead of making one up
+///
+/// Without one, the private cookies holding participant IDs can't be read after a restart, so
+/// returning participants would be registered again under new IDs.
+fn secret_key_configured() -> Result<bool> {
+    if env::var("ROCKET_SECRET_KEY").is_ok() {
+        return Ok(true);
+    }
+    let mut config = String::new();
+    match File::open("Rocket.toml") {
+        Ok(mut file) => { file.read_to_string(&mut config)?; }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into())
+    }
+    Ok(config.lines().any(|line| line.trim_left().starts_with("secret_key")))
+}
+
+fn try_main() -> Result<!> {
+    if !secret_key_configured()? {
+        if cfg!(debug_assertions) {
+            println!("WARNING: no secret key configured, participants will get new IDs after a restart (see Rocket.toml)");
+        } else {
+            bail!("no secret key configured: set secret_key in Rocket.toml or ROCKET_SECRET_KEY (e.g. to the output of `openssl rand -base64 32`)");
+        }
+    }
+
+    let (studies, participants) = load()?;
 
     println!("Launching rocket...");
-    Err(rocket::ignite()
-        .mount("/", routes![routes::index, routes::get_file, routes::list,
+    let mut rocket = rocket::ignite();
+    if settings::MOCK_PLATFORM {
+        rocket = rocket.mount("/mock_platform", routes![mock_platform::index, mock_platform::complete]);
+    }
+    Err(rocket
+        .mount("/api/v1", routes![api::login, api::config,
+                                  api::next, api::next_login, api::onboarding, api::onboarding_login,
+                                  api::practice, api::practice_login, api::rate, api::rate_login,
+                                  api::label, api::label_login, api::compare, api::compare_login,
+                                  api::rank, api::rank_login,
+                                  api::report, api::report_login, api::study_progress, api::study_progress_login,
+                                  api::surfaces, api::surfaces_login])
+        .mount("/", routes![routes::index, routes::get_file,
+                            routes::list, routes::list_login, routes::list_json, routes::list_json_login,
+                            routes::exclusion, routes::exclusion_login,
+                            routes::default_list, routes::default_random,
                             routes::login_from_query, routes::login_from_header, routes::logged_in,
+                            routes::admin_login, routes::admin_logged_in, routes::admin_logout,
+                            routes::admin_participants, routes::admin_participants_login,
+                            routes::admin_participants_json, routes::admin_participants_json_login,
+                            routes::admin_status, routes::admin_status_login, routes::admin_merge, routes::admin_merge_login,
+                            routes::admin_withdraw, routes::admin_withdraw_login,
+                            routes::admin_surface, routes::admin_surface_login,
                             routes::episode, routes::episode_login,
                             routes::random, routes::random_login,
-                            routes::rate, routes::rate_login, routes::report, routes::report_login,
+                            routes::onboarding, routes::onboarding_login,
+                            routes::rate, routes::rate_login, routes::practice, routes::practice_login, routes::report, routes::report_login,
+                            routes::compare, routes::compare_login, routes::rank, routes::rank_login,
+                            routes::label, routes::label_login,
+                            routes::export_bradley_terry, routes::export_bradley_terry_login,
+                            routes::export_implied_pairs, routes::export_implied_pairs_login,
+                            routes::export_label_agreement, routes::export_label_agreement_login,
+                            routes::admin_reliability, routes::admin_reliability_login,
+                            routes::export_reliability, routes::export_reliability_login,
+                            routes::export_dataset, routes::export_dataset_login,
+                            routes::export_long, routes::export_long_login,
+                            routes::export_long_dictionary, routes::export_long_dictionary_login,
+                            routes::export_wide, routes::export_wide_login,
+                            routes::export_wide_dictionary, routes::export_wide_dictionary_login,
+                            routes::dashboard, routes::dashboard_login,
+                            routes::diagnostics, routes::diagnostics_login,
+                            routes::export_rater_exclusions, routes::export_rater_exclusions_login,
+                            routes::save_rater_exclusions, routes::save_rater_exclusions_login,
+                            routes::admin_agreement, routes::admin_agreement_login,
+                            routes::admin_agreement_surfaces, routes::admin_agreement_surfaces_login,
+                            routes::export_agreement, routes::export_agreement_login,
+                            routes::export_surface_agreement, routes::export_surface_agreement_login,
                            ])
-        .manage(surfaces)
-        .manage(Mutex::new(reports))
-        .manage(Mutex::new(users))
+        .catch(errors![catchers::bad_request, catchers::unauthorized, catchers::forbidden,
+                        catchers::not_found, catchers::unprocessable_entity, catchers::internal_error])
+        .manage(studies)
+        .manage(participants)
         .attach(Template::fairing())
         .launch())?;
 
     unreachable!();
 }
 
-pub fn output_file<P: AsRef<Path>, F: FnOnce(csv::Reader<File>) -> Result<()>>(p: P, headers: &[&str], process: F) -> Result<()> {
-    println!("\treading file {:?}", p.as_ref());
+pub fn output_file<P: AsRef<Path>, H: AsRef<str>, F: FnOnce(csv::Reader<File>) -> Result<()>>(p: P, headers: &[H], process: F) -> Result<()> {
+    let p = p.as_ref();
+    println!("\treading file {:?}", p);
 
     let mut file = OpenOptions::new()
         .create(true)
@@ -132,11 +308,14 @@ pub fn output_file<P: AsRef<Path>, F: FnOnce(csv::Reader<File>) -> Result<()>>(p
         .open(p)?;
 
     if file.metadata()?.len() == 0 {
-        csv::Writer::from_writer(&file).write_record(headers)?;
+        csv::Writer::from_writer(&file).write_record(headers.iter().map(|h| h.as_ref()))?;
     }
 
     file.seek(SeekFrom::Start(0))?;
-    let csv = csv::Reader::from_reader(file);
+    let mut csv = csv::Reader::from_reader(file);
+    if !csv.headers()?.iter().eq(headers.iter().map(|h| h.as_ref())) {
+        bail!("unexpected columns in {:?} (move it aside if the study configuration changed)", p);
+    }
     process(csv)
 }
 
@@ -0,0 +1,43 @@
+use rand::{self, Rng};
+use rocket::State;
+use rocket_contrib::Template;
+
+use participants::Participants;
+use structs::Submission;
+use study::Studies;
+
+/// Studies that can be started from the mock platform
+fn studies_json(studies: &Studies) -> Vec<::serde_json::Value> {
+    studies.iter()
+           .map(|s| json!({ "id": s.config.id, "title": s.config.title }))
+           .collect()
+}
+
+/// Entry page with links to every study for a fresh random worker ID
+#[get("/")]
+pub fn index(studies: State<Studies>) -> Template {
+    let worker = format!("MOCK{:06}", rand::thread_rng().gen_range(0, 1_000_000));
+    Template::render("mock_platform",
+                     json!({
+                         "worker": worker,
+                         "studies": studies_json(&studies)
+                     }))
+}
+
+/// Return URL target: check a submitted completion code against the worker's participant ID
+#[get("/complete?<submission>")]
+pub fn complete(studies: State<Studies>, participants: State<Participants>, submission: Submission) -> Template {
+    let id = participants.worker_id(&submission.worker);
+    let study = id.and_then(|id| studies.iter().find(|s| s.completion_code(&id) == submission.code));
+    Template::render("mock_platform",
+                     json!({
+                         "worker": submission.worker,
+                         "studies": studies_json(&studies),
+                         "submission": {
+                             "worker": submission.worker,
+                             "code": submission.code,
+                             "valid": study.is_some(),
+                             "study": study.map(|s| s.config.title)
+                         }
+                     }))
+}
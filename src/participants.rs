@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rand::{self, Rng};
+use serde_json;
+
+use errors::*;
+use settings;
+use structs::User;
+use study::Study;
+use utils::*;
+
+/// Don't rewrite the registry just to move a participant's last-seen time by less than this (seconds)
+const TOUCH_INTERVAL: u64 = 60;
+
+/// Administrative status of a participant
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all="lowercase")]
+pub enum Status {
+    /// Taking part normally
+    Active,
+    /// May keep taking part, but their data are left out of exports
+    Excluded,
+    /// Can no longer log in, and their data are left out of exports
+    Banned,
+    /// Asked for their data to be deleted (which it has been)
+    Withdrawn,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status::Active
+    }
+}
+
+impl Status {
+    /// Parse the value of an admin form button
+    pub fn from_action(action: &str) -> Result<Status> {
+        match action {
+            "active" => Ok(Status::Active),
+            "excluded" => Ok(Status::Excluded),
+            "banned" => Ok(Status::Banned),
+            _ => Err(ErrorKind::BadParam("unknown participant status").into())
+        }
+    }
+}
+
+/// Registry entry for one participant
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Participant {
+    /// Server-generated ID (used in all output files)
+    pub id: String,
+    /// Display name chosen at login
+    pub name: String,
+    /// Worker ID on the crowdsourcing platform the participant came from
+    #[serde(default)]
+    pub worker: Option<String>,
+    /// First login (seconds since the Unix epoch)
+    pub first_seen: u64,
+    /// Last request (seconds since the Unix epoch)
+    pub last_seen: u64,
+    /// Administrative status
+    #[serde(default)]
+    pub status: Status,
+    /// Participant this one was merged into as a duplicate
+    #[serde(default)]
+    pub merged_into: Option<String>,
+}
+
+/// Registered participants (managed state)
+///
+/// Each participant gets a random ID when they first log in, which is what the output files
+/// record. The display name they typed is kept separately and must be unique. The registry is
+/// saved to `settings::PARTICIPANTS` whenever it changes.
+pub struct Participants {
+    /// Registry entries by participant ID
+    registry: Mutex<BTreeMap<String, Participant>>
+}
+
+/// Names are compared without regard to case or surrounding whitespace
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// Write the registry to disk (via a temporary file, so a crash can't leave it half-written)
+fn save(registry: &BTreeMap<String, Participant>) -> Result<()> {
+    let path = Path::new(settings::PARTICIPANTS);
+    let tmp = path.with_extension("json.tmp");
+    serde_json::to_writer_pretty(File::create(&tmp)?, &registry.values().collect::<Vec<_>>())
+        .chain_err(|| ErrorKind::Parse(tmp.clone()))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Follow merges to the participant that finally absorbed an ID
+///
+/// Merges are checked for cycles when they are made, but a hand-edited registry could still contain
+/// one, so the walk gives up after visiting every entry.
+fn canonical<'a>(registry: &'a BTreeMap<String, Participant>, id: &str) -> Option<&'a Participant> {
+    let mut p = registry.get(id);
+    for _ in 0..registry.len() {
+        match p {
+            Some(&Participant { merged_into: Some(ref into), .. }) => p = registry.get(into),
+            _ => return p
+        }
+    }
+    None
+}
+
+/// Fail unless `from` can be merged into `into`
+fn check_merge(registry: &BTreeMap<String, Participant>, from: &str, into: &str) -> Result<()> {
+    if !registry.contains_key(from) {
+        return Err(ErrorKind::BadParam("unknown participant").into());
+    }
+    // the chain of merges starting at `into` must not lead back to `from`
+    let mut id = into;
+    for _ in 0..registry.len() {
+        if id == from {
+            return Err(ErrorKind::BadParam("cannot merge a participant into themselves").into());
+        }
+        match registry.get(id) {
+            None => return Err(ErrorKind::BadParam("unknown merge target").into()),
+            Some(&Participant { merged_into: Some(ref next), .. }) => id = next,
+            Some(p) if p.status == Status::Withdrawn => return Err(ErrorKind::BadParam("cannot merge into a withdrawn participant").into()),
+            Some(_) => return Ok(())
+        }
+    }
+    Err(ErrorKind::BadParam("merge target is part of a cycle").into())
+}
+
+impl Participants {
+    /// Restore the registry from the participants file
+    pub fn load() -> Result<Participants> {
+        let path = Path::new(settings::PARTICIPANTS);
+        println!("\treading file {:?}", path);
+        let registry = match File::open(path) {
+            Ok(file) => {
+                let list: Vec<Participant> = serde_json::from_reader(file).chain_err(|| ErrorKind::Parse(path.to_owned()))?;
+                list.into_iter().map(|p| (p.id.clone(), p)).collect()
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into())
+        };
+        println!("\t{} participants", registry.len());
+        Ok(Participants { registry: Mutex::new(registry) })
+    }
+
+    /// Snapshot of all registry entries
+    pub fn list(&self) -> Vec<Participant> {
+        self.registry.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Display name of a participant
+    pub fn name(&self, id: &str) -> Option<String> {
+        self.registry.lock().unwrap().get(id).map(|p| p.name.clone())
+    }
+
+    /// Look up a participant who may take part (following merges), and update their last-seen time
+    pub fn user(&self, id: &str) -> Option<User> {
+        let mut registry = self.registry.lock().unwrap();
+        let id = match canonical(&registry, id) {
+            Some(p) if p.status == Status::Active || p.status == Status::Excluded => p.id.clone(),
+            _ => return None
+        };
+
+        let now = timestamp();
+        let user = {
+            let p = registry.get_mut(&id).unwrap();
+            if now < p.last_seen + TOUCH_INTERVAL {
+                return Some(User { id, name: p.name.clone(), worker: p.worker.clone() });
+            }
+            p.last_seen = now;
+            User { id: id.clone(), name: p.name.clone(), worker: p.worker.clone() }
+        };
+        if let Err(e) = save(&registry) {
+            println!("ERROR: could not save participants: {:?}", e);
+        }
+        Some(user)
+    }
+
+    /// ID of the participant with this crowdsourcing worker ID
+    pub fn worker_id(&self, worker: &str) -> Option<String> {
+        let registry = self.registry.lock().unwrap();
+        registry.values()
+                .find(|p| p.worker.as_ref().map(|w| &w[..]) == Some(worker))
+                .and_then(|p| canonical(&registry, &p.id))
+                .map(|p| p.id.clone())
+    }
+
+    /// ID of the participant who came from a crowdsourcing platform with this worker ID, registering
+    /// them if this is their first visit
+    pub fn worker(&self, worker: &str) -> Result<String> {
+        if let Some(id) = self.worker_id(worker) {
+            return Ok(id);
+        }
+        self.insert(worker, Some(worker.to_owned()))
+    }
+
+    /// ID of the participant who registered a display name
+    pub fn id_of(&self, name: &str) -> Option<String> {
+        let registry = self.registry.lock().unwrap();
+        registry.values()
+                .find(|p| p.merged_into.is_none() && p.status != Status::Withdrawn && same_name(&p.name, name))
+                .map(|p| p.id.clone())
+    }
+
+    /// Register a new participant and return their ID
+    pub fn register(&self, name: &str) -> Result<String> {
+        self.insert(name, None)
+    }
+
+    fn insert(&self, name: &str, worker: Option<String>) -> Result<String> {
+        let mut registry = self.registry.lock().unwrap();
+        let id = loop {
+            let id = format!("P{:08X}", rand::thread_rng().gen::<u32>());
+            if !registry.contains_key(&id) {
+                break id;
+            }
+        };
+
+        let now = timestamp();
+        registry.insert(id.clone(), Participant {
+            id: id.clone(),
+            name: name.trim().to_owned(),
+            worker,
+            first_seen: now,
+            last_seen: now,
+            status: Status::Active,
+            merged_into: None,
+        });
+        save(&registry)?;
+        Ok(id)
+    }
+
+    /// Fail unless a participant is registered
+    pub fn ensure_known(&self, id: &str) -> Result<()> {
+        if self.registry.lock().unwrap().contains_key(id) {
+            Ok(())
+        } else {
+            Err(ErrorKind::BadParam("unknown participant").into())
+        }
+    }
+
+    /// Fail unless `from` can be merged into `into` (both registered, `into` not withdrawn, and no cycle)
+    pub fn check_merge(&self, from: &str, into: &str) -> Result<()> {
+        let registry = self.registry.lock().unwrap();
+        check_merge(&registry, from, into)
+    }
+
+    /// Change a participant's administrative status
+    pub fn set_status(&self, id: &str, status: Status) -> Result<()> {
+        let mut registry = self.registry.lock().unwrap();
+        registry.get_mut(id).ok_or(ErrorKind::BadParam("unknown participant"))?.status = status;
+        save(&registry)
+    }
+
+    /// Record that `from` is a duplicate of `into` (logins as `from` continue as `into`)
+    pub fn merge(&self, from: &str, into: &str) -> Result<()> {
+        let mut registry = self.registry.lock().unwrap();
+        check_merge(&registry, from, into)?;
+        registry.get_mut(from).unwrap().merged_into = Some(into.to_owned());
+        save(&registry)
+    }
+
+    /// Mark a participant as withdrawn and forget their name
+    pub fn withdraw(&self, id: &str) -> Result<()> {
+        let mut registry = self.registry.lock().unwrap();
+        {
+            let p = registry.get_mut(id).ok_or(ErrorKind::BadParam("unknown participant"))?;
+            p.status = Status::Withdrawn;
+            p.name = String::new();
+            p.worker = None;
+        }
+        save(&registry)
+    }
+
+    /// Participants whose data must be left out of exports
+    pub fn flagged(&self) -> HashSet<String> {
+        self.registry.lock().unwrap()
+                     .values()
+                     .filter(|p| p.status == Status::Excluded || p.status == Status::Banned)
+                     .map(|p| p.id.clone())
+                     .collect()
+    }
+
+    /// Copy the exclusion flags into the studies' user tables
+    pub fn apply(&self, studies: &[Study]) {
+        let flagged = self.flagged();
+        for study in studies {
+            for (id, info) in study.users.lock().unwrap().iter_mut() {
+                info.flagged = flagged.contains(id);
+            }
+        }
+    }
+}
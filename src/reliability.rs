@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use analysis::{self, Estimate};
+use errors::*;
+use settings;
+use structs::*;
+use study::{Study, Mode};
+
+/// Number of Likert scale points
+const LEVELS: usize = 5;
+
+/// Pairs of raters must have rated at least this many surfaces in common to get a Spearman correlation
+const MIN_OVERLAP: usize = 3;
+
+/// Raters are only added to the ICC matrix while it still covers at least this many surfaces
+const ICC_MIN_SURFACES: usize = 10;
+
+/// Ratings of one surface on one dimension: (rater index, rating) pairs
+type Unit = Vec<(usize, u8)>;
+
+/// Reliability of one rating dimension within one group of surfaces
+#[derive(Serialize)]
+pub struct Reliability {
+    /// Study ID
+    pub study: &'static str,
+    /// End-effector type (`None` for all surfaces of the study)
+    pub flow: Option<FlowType>,
+    /// Questionnaire dimension
+    pub question: &'static str,
+    /// Number of surfaces rated at least twice
+    pub surfaces: usize,
+    /// Number of ratings on those surfaces
+    pub ratings: usize,
+    /// Number of raters contributing to those surfaces
+    pub raters: usize,
+    /// Krippendorff's alpha (ordinal)
+    pub alpha: Estimate,
+    /// ICC(2,k) over the largest complete block of raters and surfaces found
+    pub icc: Estimate,
+    /// Number of raters (k) in the ICC block
+    pub icc_raters: usize,
+    /// Number of surfaces in the ICC block
+    pub icc_surfaces: usize,
+    /// Mean Spearman correlation over pairs of raters with enough surfaces in common
+    pub spearman: Estimate,
+    /// Number of rater pairs averaged
+    pub spearman_pairs: usize,
+}
+
+/// Mean pairwise Spearman correlation between raters, and the number of pairs it averages
+fn mean_spearman(units: &[&Unit]) -> (f64, usize) {
+    let mut pairs = HashMap::<(usize, usize), Vec<(f64, f64)>>::new();
+    for unit in units {
+        for &(a, x) in unit.iter() {
+            for &(b, y) in unit.iter() {
+                if a < b {
+                    pairs.entry((a, b)).or_insert_with(Vec::new).push((x as f64, y as f64));
+                }
+            }
+        }
+    }
+
+    let rhos = pairs.values()
+                    .filter(|p| p.len() >= MIN_OVERLAP)
+                    .map(|p| analysis::spearman(p))
+                    .filter(|r| !r.is_nan())
+                    .collect::<Vec<_>>();
+    (rhos.iter().sum::<f64>() / rhos.len() as f64, rhos.len())
+}
+
+/// Complete surfaces × raters matrix for ICC(2,k)
+///
+/// Raters are considered in decreasing order of their number of ratings, and each one is kept if the
+/// surfaces rated by everyone kept so far would still number at least `ICC_MIN_SURFACES`.
+fn icc_matrix(units: &[Unit]) -> (Vec<Vec<f64>>, usize) {
+    let mut counts = BTreeMap::<usize, usize>::new();
+    for unit in units {
+        for &(r, _) in unit {
+            *counts.entry(r).or_insert(0) += 1;
+        }
+    }
+    let mut order = counts.into_iter().collect::<Vec<_>>();
+    order.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let rated_by = |r: usize| -> BTreeSet<usize> {
+        units.iter()
+             .enumerate()
+             .filter(|&(_, u)| u.iter().any(|&(s, _)| s == r))
+             .map(|(i, _)| i)
+             .collect()
+    };
+
+    let mut raters = vec![];
+    let mut common = BTreeSet::new();
+    for (r, _) in order {
+        let next = if raters.is_empty() {
+            rated_by(r)
+        } else {
+            common.intersection(&rated_by(r)).cloned().collect()
+        };
+        if next.len() >= ICC_MIN_SURFACES {
+            raters.push(r);
+            common = next;
+        }
+    }
+    if raters.len() < 2 {
+        return (vec![], raters.len());
+    }
+
+    let rows = common.into_iter()
+                     .map(|i| raters.iter()
+                                    .map(|&r| units[i].iter().find(|&&(s, _)| s == r).unwrap().1 as f64)
+                                    .collect())
+                     .collect();
+    (rows, raters.len())
+}
+
+/// Reliability statistics for one group of units
+fn group(study: &Study, flow: Option<FlowType>, question: &'static str, units: Vec<Unit>) -> Reliability {
+    let samples = settings::BOOTSTRAP_SAMPLES;
+    let confidence = settings::CONFIDENCE;
+    let seed = study.config.sampling.seed;
+
+    let units = units.into_iter().filter(|u| u.len() >= 2).collect::<Vec<_>>();
+    let raters = units.iter().flat_map(|u| u.iter().map(|&(r, _)| r)).collect::<HashSet<_>>();
+
+    let alpha = analysis::bootstrap(&units, samples, confidence, seed, |units| {
+        let values = units.iter()
+                          .map(|u| u.iter().map(|&(_, x)| x as usize - 1).collect::<Vec<_>>())
+                          .collect::<Vec<_>>();
+        analysis::krippendorff_alpha_ordinal(&values.iter().map(|v| &v[..]).collect::<Vec<_>>(), LEVELS)
+    });
+
+    let (matrix, icc_raters) = icc_matrix(&units);
+    let icc = analysis::bootstrap(&matrix, samples, confidence, seed, |rows| {
+        analysis::icc_2k(&rows.iter().map(|r| &r[..]).collect::<Vec<_>>())
+    });
+
+    let spearman_pairs = mean_spearman(&units.iter().collect::<Vec<_>>()).1;
+    let spearman = analysis::bootstrap(&units, samples, confidence, seed, |units| mean_spearman(units).0);
+
+    Reliability {
+        study: study.config.id,
+        flow,
+        question,
+        surfaces: units.len(),
+        ratings: units.iter().map(|u| u.len()).sum(),
+        raters: raters.len(),
+        alpha,
+        icc,
+        icc_raters,
+        icc_surfaces: matrix.len(),
+        spearman,
+        spearman_pairs,
+    }
+}
+
+/// Reliability of every questionnaire dimension of every Likert study, for all surfaces and for
+/// each end-effector type
+pub fn reliability(studies: &[Study]) -> Result<Vec<Reliability>> {
+    let mut results = vec![];
+    for study in studies {
+        match study.config.mode {
+            Mode::Likert => {}
+            _ => continue
+        }
+
+        let excluded = study.excluded_users();
+        let mut raters = HashMap::new();
+        let mut surfaces = BTreeMap::<(Datestamp, FlowType, u32), Vec<(usize, HashMap<String, Likert>)>>::new();
+        for (surface, user) in study.ratings()? {
+            if excluded.contains(&user) {
+                continue;
+            }
+            let next = raters.len();
+            let rater = *raters.entry(user).or_insert(next);
+            surfaces.entry((surface.date, surface.flow, surface.num))
+                    .or_insert_with(Vec::new)
+                    .push((rater, surface.ratings));
+        }
+
+        let flows = surfaces.keys().map(|k| k.1).collect::<BTreeSet<_>>();
+        for flow in Some(None).into_iter().chain(flows.into_iter().map(Some)) {
+            for question in study.config.questionnaire {
+                let units = surfaces.iter()
+                                    .filter(|&(k, _)| flow.map_or(true, |f| f == k.1))
+                                    .map(|(_, ratings)| {
+                                        ratings.iter()
+                                               .filter_map(|&(r, ref answers)| answers.get(question.short).map(|a| (r, a.0)))
+                                               .collect()
+                                    })
+                                    .collect();
+                results.push(group(study, flow, question.short, units));
+            }
+        }
+    }
+    Ok(results)
+}
@@ -1,25 +1,376 @@
-use std::fs::OpenOptions;
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
 use std::io::{self, Write};
 use std::path::PathBuf;
 use std::time::SystemTime;
 
+use csv;
 use rocket::State;
-use rocket::http::{Cookie, Cookies, Status};
+use rocket::http::{Cookie, Cookies, ContentType};
 use rocket::http::uri::URI;
 use rocket::request::Form;
-use rocket::response::{Failure, Redirect, NamedFile};
+use rocket::response::{Redirect, NamedFile};
+use rocket::response::content::Content;
 use rocket_contrib::Template;
-use rand::distributions::{IndependentSample, Range};
+use rand::{self, Rng};
+use flow::FlowCmd;
 
-use rand;
+use agreement;
+use analysis::Estimate;
+use auth::Admin;
+use dashboard;
+use diagnostics;
+use export;
+use participants::{self, Participants};
+use reliability;
+use sampling::Order;
 use settings;
 use errors::*;
 use structs::*;
+use study::{self, Study, Studies, Mode, Question, Crowdsourcing};
 use utils::*;
 
+/// Append a response to the trial log, along with the seed and order position that produced it
+fn log_trial(study: &Study, user: &User, info: &UserInfo, date: Datestamp, flow: FlowType, num: u32, response: &str) -> Result<()> {
+    let (seed, position) = match info.order {
+        Some(ref order) => (order.seed.to_string(),
+                            order.position(&study.surfaces, date, flow, num).map(|p| p.to_string()).unwrap_or_default()),
+        None => (String::new(), String::new())
+    };
+
+    let mut file = study.append(settings::TRIALS)?;
+    writeln!(&mut file, "{},{},{},{},{},{},{},{}{}", user.id, date, flow, num, response, seed, position, timestamp(),
+             study.condition_column(info))?;
+    Ok(())
+}
+
+/// Make sure the user has a trial order (and condition) in this study, recording them if they were just assigned
+///
+/// Conditions are balanced by drawing among those with the fewest participants so far (ties are broken
+/// with the user's seeded RNG, so the assignment can be reproduced).
+pub fn ensure_order(study: &Study, users: &mut HashMap<String, UserInfo>, user: &User) -> Result<()> {
+    if users.get(&user.id).map_or(true, |info| info.order.is_none()) {
+        let assignment = users.values().filter(|info| info.order.is_some()).count();
+        let order = Order::new(study.config, &user.id, assignment, &study.surfaces);
+
+        let condition = if study.config.conditions.is_empty() {
+            None
+        } else {
+            let mut counts = vec![0; study.config.conditions.len()];
+            for c in users.values().filter_map(|info| info.condition) {
+                counts[c] += 1;
+            }
+            let fewest = *counts.iter().min().unwrap();
+            let candidates = (0..counts.len()).filter(|&c| counts[c] == fewest).collect::<Vec<_>>();
+            Some(*order.condition_rng().choose(&candidates).unwrap())
+        };
+
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        user_info.order = Some(order);
+        user_info.condition = condition;
+        let order = user_info.order.as_ref().unwrap();
+        let mut file = study.append(settings::ORDERS)?;
+        writeln!(&mut file, "{},{},{}{}", user.id, order.seed, order.assignment, study.condition_column(user_info))?;
+    }
+    Ok(())
+}
+
+/// Parse an episode's flow file from the first datadir that has it, returning the surface and the
+/// path of the flow file
+fn load_episode(date: Datestamp, flow: FlowType, idx: u32) -> Result<(SurfaceData, PathBuf)> {
+    let mut data = None;
+    for dir in settings::DATADIRS {
+        let mut path = PathBuf::from(dir);
+        path.push(format!("{}", date.0));
+        path.push(format!("{}", flow));
+        path.push(format!("{}", idx));
+        path.push(format!("{}.flow", flow));
+
+        data = match SurfaceData::from_flow_file(date, flow, idx, &path) {
+            Ok(d) => Some((d, path)),
+            Err(e) => match *e.kind() {
+                ErrorKind::Io(ref ioe) => match ioe.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => break
+                },
+                _ => break
+            }
+        };
+    }
+
+    Ok(data.ok_or(io::Error::new(io::ErrorKind::NotFound, "episode not found in any datadir"))?)
+}
+
+/// Template variables describing a participant's condition
+fn condition_context(study: &Study, info: Option<&UserInfo>) -> (::serde_json::Value, HashMap<&'static str, bool>) {
+    match info.and_then(|info| study.condition(info)) {
+        Some(condition) => (condition.name.into(), condition.flags.iter().map(|&f| (f, true)).collect()),
+        None => (::serde_json::Value::Null, HashMap::new())
+    }
+}
+
+/// Questionnaire along with the experimenter's rating of a surface for each question
+fn questions_with_ratings(study: &Study, surface: &SurfaceData) -> Vec<::serde_json::Value> {
+    study.config.questionnaire.iter()
+         .map(|q| {
+             let mut json = ::serde_json::to_value(q).unwrap();
+             json.as_object_mut().unwrap().insert("experimenter".into(), json!(surface.ratings.get(q.short)));
+             json
+         })
+         .collect()
+}
+
+/// Onboarding step (consent, demographics or instructions) the user still has to complete, if any
+pub fn onboarding_step(info: &UserInfo) -> Option<&'static str> {
+    if info.consent.as_ref().map_or(true, |&(ref version, _)| version != settings::CONSENT_VERSION) {
+        Some("consent")
+    } else if settings::DEMOGRAPHICS.iter().any(|d| !info.demographics.contains_key(d.short)) {
+        Some("demographics")
+    } else if !info.instructed {
+        Some("instructions")
+    } else {
+        None
+    }
+}
+
+/// Onboarding page the user still has to complete, if any
+fn onboarding_page(study: &Study, user: &User, info: &UserInfo, error: &str) -> Option<Template> {
+    let template = match onboarding_step(info) {
+        Some(step) => step,
+        None => return None
+    };
+
+    Some(Template::render(template,
+                          json!({
+                              "error": error,
+                              "study": study.config.id,
+                              "title": study.config.title,
+                              "user": user,
+                              "version": settings::CONSENT_VERSION,
+                              "demographics": settings::DEMOGRAPHICS,
+                              "questions": study.config.questionnaire,
+                              "practice": study.practice.len()
+                          })))
+}
+
+/// Append a completed onboarding step to the onboarding log
+fn log_onboarding(study: &Study, user: &User, info: &UserInfo, step: &str, answer: &str) -> Result<()> {
+    let mut record = vec![user.id.clone(), step.to_owned(), answer.to_owned(), timestamp().to_string()];
+    study.push_condition(info, &mut record);
+    let mut csv = csv::Writer::from_writer(study.append(settings::ONBOARDING)?);
+    csv.write_record(&record)?;
+    Ok(())
+}
+
+/// Completion code of a crowdsourcing participant who has reached the quota, and the URL to return
+/// to the platform with
+///
+/// The code is recorded the first time it is issued.
+pub fn completion(study: &Study, user: &User, info: &mut UserInfo, crowd: &Crowdsourcing) -> Result<(String, String)> {
+    let code = study.completion_code(&user.id);
+    let worker = user.worker.clone().unwrap_or_default();
+    if !info.completed {
+        let mut record = vec![user.id.clone(), worker.clone(), code.clone(), timestamp().to_string()];
+        study.push_condition(info, &mut record);
+        let mut csv = csv::Writer::from_writer(study.append(settings::COMPLETIONS)?);
+        csv.write_record(&record)?;
+        info.completed = true;
+    }
+
+    let url = crowd.return_url.replace("{code}", &code)
+                              .replace("{worker}", &URI::percent_encode(&worker));
+    Ok((code, url))
+}
+
+/// Page shown once a crowdsourcing participant has reached the quota, with their completion code
+fn completion_page(study: &Study, user: &User, info: &mut UserInfo, crowd: &Crowdsourcing) -> Result<Template> {
+    let (code, url) = completion(study, user, info, crowd)?;
+    Ok(Template::render("complete",
+                        json!({
+                            "title": study.config.title,
+                            "user": user,
+                            "code": code,
+                            "url": url
+                        })))
+}
+
+/// Completion page for a crowdsourcing participant who has reached the quota, if they have
+fn quota_page(study: &Study, user: &User) -> Result<Option<Template>> {
+    let crowd = match study.config.crowdsourcing {
+        Some(ref crowd) => crowd,
+        None => return Ok(None)
+    };
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    if user_info.trials() >= crowd.quota {
+        Ok(Some(completion_page(study, user, user_info, crowd)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Page shown when there are no trials left for a user
+///
+/// Crowdsourcing participants get their completion code even if they ran out before the quota.
+fn finished_page(study: &Study, user: &User) -> Result<Template> {
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    match study.config.crowdsourcing {
+        Some(ref crowd) => completion_page(study, user, user_info, crowd),
+        None => Ok(Template::render("finished", json!({ "title": study.config.title, "user": user })))
+    }
+}
+
+/// Render one of the study's practice trials
+fn render_practice(study: &Study, user: &User, info: &UserInfo, error: &str) -> Template {
+    let i = info.practiced;
+    let surface = &study.practice[i];
+    let (condition, flags) = condition_context(study, Some(info));
+    Template::render("episode",
+                     json!({
+                         "condition": condition,
+                         "flags": flags,
+                         "practice": true,
+                         "trial": i + 1,
+                         "trials": study.practice.len(),
+                         "rate_error": error,
+                         "study": study.config.id,
+                         "questions": questions_with_ratings(study, surface),
+                         "user": user,
+                         "surface": surface,
+                         "date": surface.date.0,
+                         "flow": surface.flow.to_string(),
+                         "idx": surface.num
+                     }))
+}
+
+/// Render a pairwise comparison trial
+fn render_pair(study: &Study, user: &User, a: usize, b: usize, error: &str) -> Template {
+    Template::render("pair",
+                     json!({
+                         "error": error,
+                         "study": study.config.id,
+                         "questions": study.config.questionnaire,
+                         "user": user,
+                         "a": study.surfaces[a],
+                         "b": study.surfaces[b]
+                     }))
+}
+
+/// Schedule the next pairwise comparison for a user (`None` once they have compared every eligible pair)
+///
+/// Pairs the user has already compared and excluded surfaces are skipped, and among a sample of
+/// candidate pairs the one compared by the fewest users wins. The pair is remembered as the one
+/// being shown.
+pub fn choose_pair(study: &Study, user: &User) -> Result<Option<(usize, usize)>> {
+    let mut users = study.users.lock().unwrap();
+    let reports = study.reports.lock().unwrap();
+    let comparisons = study.comparisons.lock().unwrap();
+    ensure_order(study, &mut users, user)?;
+    let user_info = users.get_mut(&user.id).unwrap();
+    let mut rng = user_info.order.as_ref().unwrap().rng_at(user_info.compared.len());
+
+    let eligible = study.eligible(&reports);
+    let mut best: Option<((usize, usize), u32)> = None;
+    {
+        let mut consider = |pair: (usize, usize)| {
+            if !user_info.compared.contains(&pair) {
+                let count = comparisons.get(&pair).cloned().unwrap_or(0);
+                if best.map_or(true, |(_, c)| count < c) {
+                    best = Some((pair, count));
+                }
+            }
+        };
+        if eligible.len() >= 2 {
+            for _ in 0..settings::PAIR_CANDIDATES {
+                let a = *rng.choose(&eligible).unwrap();
+                let b = *rng.choose(&eligible).unwrap();
+                if a != b {
+                    consider(if a < b { (a, b) } else { (b, a) });
+                }
+            }
+        }
+        // the sample can miss the last few uncompared pairs, so make sure none are left before giving up
+        if best.is_none() {
+            for (n, &a) in eligible.iter().enumerate() {
+                for &b in &eligible[n + 1..] {
+                    consider((a, b));
+                }
+            }
+        }
+    }
+
+    let (a, b) = match best {
+        Some((pair, _)) => pair,
+        None => return Ok(None)
+    };
+    user_info.pair = Some((a, b));
+    Ok(Some(if rng.gen() { (b, a) } else { (a, b) }))
+}
+
+/// Show the next pairwise comparison, or the finished page once there are no pairs left
+fn pair_trial(study: &Study, user: &User) -> Result<Template> {
+    if let Some(page) = quota_page(study, user)? {
+        return Ok(page);
+    }
+    match choose_pair(study, user)? {
+        Some((a, b)) => Ok(render_pair(study, user, a, b, "")),
+        None => finished_page(study, user)
+    }
+}
+
+/// Render a ranking trial
+fn render_ranking(study: &Study, user: &User, shown: &[usize], question: &Question, error: &str) -> Template {
+    let surfaces = shown.iter().map(|&i| &study.surfaces[i]).collect::<Vec<_>>();
+    let keys = surfaces.iter().map(|s| format!("{}/{}/{}", s.date, s.flow, s.num)).collect::<Vec<_>>();
+    Template::render("rank",
+                     json!({
+                         "error": error,
+                         "study": study.config.id,
+                         "question": question,
+                         "user": user,
+                         "surfaces": surfaces,
+                         "keys": keys.join(";")
+                     }))
+}
+
+/// Choose a set of surfaces for a user to rank by one question (`None` if there aren't enough left)
+///
+/// The questions take turns, and the surfaces are a random sample of those not excluded. The trial
+/// is remembered as the one being shown.
+pub fn choose_ranking(study: &Study, user: &User, size: usize) -> Result<Option<(Vec<usize>, &'static Question)>> {
+    let questions = study.config.questionnaire;
+    let mut users = study.users.lock().unwrap();
+    let reports = study.reports.lock().unwrap();
+    ensure_order(study, &mut users, user)?;
+    let user_info = users.get_mut(&user.id).unwrap();
+    let mut rng = user_info.order.as_ref().unwrap().rng_at(user_info.rankings);
+
+    let eligible = study.eligible(&reports);
+    if eligible.len() < size {
+        return Ok(None);
+    }
+    let mut shown = rand::sample(&mut rng, eligible, size);
+    rng.shuffle(&mut shown);
+    let question = &questions[user_info.rankings % questions.len()];
+    user_info.ranking = Some((shown.clone(), question.short));
+    Ok(Some((shown, question)))
+}
+
+/// Show the next ranking trial, or the finished page once there aren't enough surfaces left
+fn ranking_trial(study: &Study, user: &User, size: usize) -> Result<Template> {
+    if let Some(page) = quota_page(study, user)? {
+        return Ok(page);
+    }
+    match choose_ranking(study, user, size)? {
+        Some((shown, question)) => Ok(render_ranking(study, user, &shown, question, "")),
+        None => finished_page(study, user)
+    }
+}
+
 #[get("/login?<refer>")]
 pub fn login_from_query(refer: Referer) -> Template {
-    Template::render("login", json!({ "redir": refer.uri }))
+    Template::render("login", json!({ "redir": refer.uri, "taken": refer.taken, "worker": refer.worker }))
 }
 
 #[get("/login")]
@@ -27,11 +378,74 @@ pub fn login_from_header(refer: Referer) -> Template {
     Template::render("login", json!({ "redir": refer.uri }))
 }
 
-#[post("/logged_in", data="<login>")]
-pub fn logged_in(mut cookies: Cookies, login: Form<Login>) -> Redirect {
-    let login = login.get();
-    cookies.add(Cookie::new("user", login.user_name.clone()));
-    Redirect::to(&login.redir)
+/// Log a participant in by worker ID or chosen name, setting the user cookie
+///
+/// Returns the participant ID, or `None` if someone else already has the name.
+pub fn sign_in(cookies: &mut Cookies, participants: &Participants, user_name: &str, worker: Option<&str>) -> Result<Option<String>> {
+    let current = cookies.get_private("user").map(|c| c.value().to_owned());
+
+    // crowdsourcing workers are identified by their worker ID, so returning workers continue where they left off
+    if let Some(worker) = worker {
+        if !worker.trim().is_empty() {
+            let id = participants.worker(worker.trim())?;
+            cookies.add_private(Cookie::new("user", id.clone()));
+            return Ok(Some(id));
+        }
+    }
+
+    match participants.id_of(user_name) {
+        // returning participant whose cookie matches the name
+        Some(id) => Ok(if Some(&id) == current.as_ref() { Some(id) } else { None }),
+
+        None => {
+            if user_name.trim().is_empty() {
+                Err::<(), _>(ErrorKind::BadParam("empty name"))?;
+            }
+            let id = participants.register(user_name)?;
+            cookies.add_private(Cookie::new("user", id.clone()));
+            Ok(Some(id))
+        }
+    }
+}
+
+handle! {
+    #[post("/logged_in", data="<login>")]
+    pub fn logged_in(mut cookies: Cookies, participants: State<Participants>, login: Form<Login>) -> Redirect {
+        let login = login.into_inner();
+
+        match sign_in(&mut cookies, &participants, &login.user_name, login.worker.as_ref().map(|w| &w[..]))? {
+            Some(_) => Ok(Redirect::to(&login.redir)),
+
+            // someone else already has this name
+            None => Ok(Redirect::to(&format!("/login?uri={}&taken={}",
+                                             URI::percent_encode(&login.redir),
+                                             URI::percent_encode(&login.user_name))))
+        }
+    }
+}
+
+#[get("/admin/login?<refer>")]
+pub fn admin_login(refer: AdminReferer) -> Template {
+    Template::render("admin_login", json!({ "redir": refer.uri, "failed": refer.failed.is_some() }))
+}
+
+#[post("/admin/logged_in", data="<login>")]
+pub fn admin_logged_in(mut cookies: Cookies, login: Form<AdminLogin>) -> Redirect {
+    let login = login.into_inner();
+    match Admin::login(&login.name, &login.password) {
+        Some(admin) => {
+            println!("Admin {} logged in", admin.name);
+            cookies.add_private(Cookie::new("admin", admin.name));
+            Redirect::to(&login.redir)
+        }
+        None => Redirect::to(&format!("/admin/login?uri={}&failed=1", URI::percent_encode(&login.redir)))
+    }
+}
+
+#[get("/admin/logout")]
+pub fn admin_logout(mut cookies: Cookies) -> &'static str {
+    cookies.remove_private(Cookie::named("admin"));
+    "Logged out."
 }
 
 #[get("/")]
@@ -59,60 +473,423 @@ handle! {
     }
 }
 
-handle! {
-    #[get("/list")]
-    pub fn list(surfaces: State<Vec<SurfaceData>>, reports: State<Reports>) -> Template {
-        let start = SystemTime::now();
+#[get("/list")]
+pub fn default_list() -> Redirect {
+    Redirect::to(&format!("/study/{}/list", settings::STUDIES[0].id))
+}
+
+#[get("/random")]
+pub fn default_random() -> Redirect {
+    Redirect::to(&format!("/study/{}/random", settings::STUDIES[0].id))
+}
+
+/// Registry entries and per-study progress of every participant (including legacy users who only
+/// appear in the output files)
+fn participants_json(studies: &[Study], participants: &Participants) -> Vec<::serde_json::Value> {
+    let mut list = participants.list();
+    for study in studies {
+        for id in study.users.lock().unwrap().keys() {
+            if !list.iter().any(|p| &p.id == id) {
+                list.push(participants::Participant {
+                    id: id.clone(),
+                    name: String::new(),
+                    worker: None,
+                    first_seen: 0,
+                    last_seen: 0,
+                    status: Default::default(),
+                    merged_into: None,
+                });
+            }
+        }
+    }
+
+    list.into_iter()
+        .map(|p| {
+            let progress = studies.iter()
+                .map(|study| {
+                    let users = study.users.lock().unwrap();
+                    match users.get(&p.id) {
+                        Some(info) => json!({
+                            "study": study.config.id,
+                            "condition": study.condition(info).map(|c| c.name),
+                            "consent": info.consent.as_ref().map(|&(ref version, _)| version.clone()),
+                            "consent_time": info.consent.as_ref().map(|&(_, time)| time),
+                            "onboarded": info.instructed,
+                            "practiced": info.practiced,
+                            "trials": info.trials(),
+                            "quota": study.config.crowdsourcing.as_ref().map(|c| c.quota),
+                            "completed": info.completed,
+                            "checks_failed": info.checks_failed,
+                            "excluded": info.excluded()
+                        }),
+                        None => json!({ "study": study.config.id })
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut json = ::serde_json::to_value(&p).unwrap();
+            json.as_object_mut().unwrap().insert("studies".into(), progress.into());
+            json
+        })
+        .collect()
+}
+
+handle_admin! {
+    #[get("/admin/participants")]
+    pub fn admin_participants/admin_participants_login(admin: Admin, studies: State<Studies>, participants: State<Participants>) -> Template {
+        Ok(Template::render("participants", json!({
+            "admin": admin.name,
+            "studies": studies.iter().map(|s| s.config.id).collect::<Vec<_>>(),
+            "participants": participants_json(&studies, &participants)
+        })))
+    }
+}
+
+handle_admin! {
+    #[get("/admin/participants.json")]
+    pub fn admin_participants_json/admin_participants_json_login(admin: Admin, studies: State<Studies>, participants: State<Participants>) -> Content<String> {
+        Ok(Content(ContentType::JSON, ::serde_json::to_string_pretty(&participants_json(&studies, &participants)).unwrap()))
+    }
+}
+
+handle_admin! {
+    #[post("/admin/participants/status", data="<form>")]
+    pub fn admin_status/admin_status_login(admin: Admin, studies: State<Studies>, participants: State<Participants>, form: Form<StatusChange>) -> Redirect {
+        let StatusChange { id, status } = form.into_inner();
+        participants.set_status(&id, participants::Status::from_action(&status)?)?;
+        participants.apply(&studies);
+        println!("Admin {} set status of {} to {}", admin.name, id, status);
+        Ok(Redirect::to("/admin/participants"))
+    }
+}
+
+handle_admin! {
+    #[post("/admin/participants/merge", data="<form>")]
+    pub fn admin_merge/admin_merge_login(admin: Admin, studies: State<Studies>, participants: State<Participants>, form: Form<Merge>) -> Redirect {
+        let Merge { from, into } = form.into_inner();
+        participants.check_merge(&from, &into)?;
+        for study in studies.iter() {
+            study.rewrite_participant(&from, Some(&into))?;
+        }
+        participants.merge(&from, &into)?;
+        participants.apply(&studies);
+        println!("Admin {} merged {} into {}", admin.name, from, into);
+        Ok(Redirect::to("/admin/participants"))
+    }
+}
+
+handle_admin! {
+    #[post("/admin/participants/withdraw", data="<form>")]
+    pub fn admin_withdraw/admin_withdraw_login(admin: Admin, studies: State<Studies>, participants: State<Participants>, form: Form<Withdrawal>) -> Redirect {
+        let Withdrawal { id, confirm } = form.into_inner();
+        if id != confirm {
+            Err::<(), _>(ErrorKind::BadParam("withdrawal not confirmed"))?;
+        }
+        participants.ensure_known(&id)?;
+        let mut rows = 0;
+        for study in studies.iter() {
+            rows += study.rewrite_participant(&id, None)?;
+        }
+        participants.withdraw(&id)?;
+        participants.apply(&studies);
+        println!("Admin {} withdrew {} ({} rows deleted)", admin.name, id, rows);
+        Ok(Redirect::to("/admin/participants"))
+    }
+}
 
-        let reports = reports.lock().unwrap();
-        let surfaces = surfaces.iter()
-            .map(|surf| {
-                let mut json = ::serde_json::to_value(surf).unwrap();
-                if reports.contains(&(surf.date, surf.flow, surf.num)) {
-                    json.as_object_mut().unwrap().insert("report".into(), true.into());
+handle_admin! {
+    #[get("/admin/<date>/<flow>/<idx>")]
+    pub fn admin_surface/admin_surface_login(admin: Admin, studies: State<Studies>, date: Datestamp, flow: Option<FlowType>, idx: u32) -> Template {
+        let flow = flow.ok_or(ErrorKind::BadParam("invalid flow type"))?;
+        let key = (date, flow, idx);
+        let (surface, path) = load_episode(date, flow, idx)?;
+
+        let parsed = parse_flow(flow, &path)?;
+        let states = parsed.states.iter()
+                                  .map(|s| json!({ "name": s.name, "commands": s.script.len() }))
+                                  .collect::<Vec<_>>();
+        let wrap_up = parsed.states.iter()
+                                   .find(|s| s.name.starts_with("Wrap up"))
+                                   .map(|s| s.script.iter()
+                                                    .filter_map(|&(ref cmd, _)| match *cmd {
+                                                        FlowCmd::Int { ref prompt, data, .. } => Some(json!({
+                                                            "prompt": prompt,
+                                                            "answer": data.map(|d| d as u8)
+                                                        })),
+                                                        _ => None
+                                                    })
+                                                    .collect::<Vec<_>>())
+                                   .unwrap_or_default();
+
+        let mut per_study = vec![];
+        for study in studies.iter() {
+            let index = study.index_of(key);
+            let ratings = study.ratings()?
+                               .into_iter()
+                               .filter(|&(ref s, _)| (s.date, s.flow, s.num) == key)
+                               .collect::<Vec<_>>();
+            let reports = study.image_reports()?
+                               .into_iter()
+                               .filter(|&(ref r, _)| (r.date, r.flow, r.num) == key)
+                               .collect::<Vec<_>>();
+            if index.is_none() && ratings.is_empty() && reports.is_empty() {
+                continue;
+            }
+
+            // match each rating with the time it was logged
+            let mut times = HashMap::<String, Vec<u64>>::new();
+            for row in study.trials()? {
+                if (row.date, row.flow, row.num) == key && row.response == "rating" {
+                    times.entry(row.user).or_insert_with(Vec::new).push(row.time);
                 }
-                json
+            }
+
+            let excluded = study.excluded_users();
+            let ratings = ratings.iter()
+                                 .map(|&(ref s, ref user)| {
+                                     let time = times.get_mut(user).and_then(|t| if t.is_empty() { None } else { Some(t.remove(0)) });
+                                     json!({
+                                         "user": user,
+                                         "excluded": excluded.contains(user),
+                                         "time": time,
+                                         "answers": study.config.questionnaire.iter()
+                                                                               .map(|q| s.ratings.get(q.short).map(|a| a.0))
+                                                                               .collect::<Vec<_>>()
+                                     })
+                                 })
+                                 .collect::<Vec<_>>();
+            let reports = reports.iter()
+                                 .map(|&(ref r, ref user)| json!({
+                                     "user": user,
+                                     "excluded": excluded.contains(user),
+                                     "dark": r.dark,
+                                     "bright": r.bright,
+                                     "blurry": r.blurry,
+                                     "grainy": r.grainy
+                                 }))
+                                 .collect::<Vec<_>>();
+
+            let (report_count, views, manual, auto, retired) = match study.reports.lock().unwrap().get(&key) {
+                Some(tally) => (tally.reports, tally.views, tally.exclude, tally.auto_excluded(&study.config.sampling),
+                                tally.excluded(&study.config.sampling)),
+                None => (0, 0, None, false, false)
+            };
+
+            let sampling = &study.config.sampling;
+            let z = sampling.adaptive.as_ref().map_or(1.96, |a| a.z);
+            let (dimensions, converged) = match study.moments.lock().unwrap().get(&key) {
+                Some(moments) => (study.config.questionnaire.iter()
+                                                            .zip(moments)
+                                                            .map(|(q, m)| json!({
+                                                                "question": q.short,
+                                                                "n": m.n,
+                                                                "mean": decimal(m.mean),
+                                                                "ci_width": decimal(m.ci_width(z))
+                                                            }))
+                                                            .collect::<Vec<_>>(),
+                                  sampling.adaptive.as_ref().map(|a| a.converged(moments))),
+                None => (vec![], None)
+            };
+
+            per_study.push(json!({
+                "id": study.config.id,
+                "title": study.config.title,
+                "in_study": index.is_some(),
+                "practice": study.config.practice.contains(&key),
+                "gold": sampling.gold.iter().any(|g| (g.date, g.flow, g.num) == key),
+                "retired": retired,
+                "reports": report_count,
+                "views": views,
+                "override": manual,
+                "auto_excluded": auto,
+                "converged": converged,
+                "dimensions": dimensions,
+                "questions": questions_with_ratings(study, &surface),
+                "ratings": ratings,
+                "image_reports": reports
+            }));
+        }
+
+        Ok(Template::render("admin_surface", json!({
+            "admin": admin.name,
+            "date": date.0,
+            "flow": flow.to_string(),
+            "idx": idx,
+            "path": path.display().to_string(),
+            "experimenter": surface.ratings,
+            "states": states,
+            "wrap_up": wrap_up,
+            "studies": per_study
+        })))
+    }
+}
+
+/// Filtered, sorted page of a study's surfaces for the admin list
+pub fn surface_page(study: &Study, query: &ListQuery) -> ::serde_json::Value {
+    let reports = study.reports.lock().unwrap();
+    let moments = study.moments.lock().unwrap();
+    let adaptive = study.config.sampling.adaptive.as_ref();
+
+    let mut rows = study.surfaces.iter()
+        .enumerate()
+        .filter_map(|(i, surf)| {
+            let key = (surf.date, surf.flow, surf.num);
+            let tally = reports.get(&key);
+            let m = moments.get(&key);
+            let ratings = m.and_then(|m| m.first()).map_or(0, |m| m.n);
+            if !query.matches(surf, tally.map_or(0, |t| t.reports), ratings) {
+                return None;
+            }
+            // surfaces missing the sort key go first (last in descending order)
+            let sort_key = match &query.sort[..] {
+                "episode" => i as f64,
+                "reports" => tally.map_or(0.0, |t| t.reports as f64),
+                "views" => tally.map_or(0.0, |t| t.views as f64),
+                "ratings" => ratings as f64,
+                "ci" => match (adaptive, m) {
+                    (Some(a), Some(m)) => a.width(m),
+                    _ => -1.0
+                },
+                question => surf.ratings.get(question).map_or(-1.0, |r| r.0 as f64)
+            };
+            Some((sort_key, i, ratings))
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        let ord = a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal);
+        if query.desc { ord.reverse() } else { ord }
+    });
+
+    let total = rows.len();
+    let pages = cmp::max((total + query.per_page - 1) / query.per_page, 1);
+    let first = (query.page - 1) * query.per_page;
+    let surfaces = rows.iter()
+        .enumerate()
+        .skip(first)
+        .take(query.per_page)
+        .map(|(position, &(_, i, ratings))| {
+            let surf = &study.surfaces[i];
+            let mut json = ::serde_json::to_value(surf).unwrap();
+            {
+                let obj = json.as_object_mut().unwrap();
+                obj.insert("position".into(), (position + 1).into());
+                obj.insert("rating_count".into(), ratings.into());
+                if let (Some(adaptive), Some(m)) = (adaptive, moments.get(&(surf.date, surf.flow, surf.num))) {
+                    obj.insert("ci_width".into(), format!("{:.2}", adaptive.width(m)).into());
+                    obj.insert("converged".into(), adaptive.converged(m).into());
+                }
+                if let Some(tally) = reports.get(&(surf.date, surf.flow, surf.num)) {
+                    if tally.reports > 0 {
+                        obj.insert("report".into(), true.into());
+                    }
+                    obj.insert("reports".into(), tally.reports.into());
+                    obj.insert("views".into(), tally.views.into());
+                    obj.insert("excluded".into(), tally.excluded(&study.config.sampling).into());
+                    obj.insert("override".into(), tally.exclude.is_some().into());
+                }
+            }
+            json
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "total": total,
+        "page": query.page,
+        "pages": pages,
+        "per_page": query.per_page,
+        "surfaces": surfaces
+    })
+}
+
+handle_admin! {
+    #[get("/study/<id>/list")]
+    pub fn list/list_login(admin: Admin, studies: State<Studies>, participants: State<Participants>, id: String, query: ListQuery) -> Template {
+        let start = SystemTime::now();
+        let study = Study::find(&studies, &id)?;
+
+        let raters = study.users.lock().unwrap()
+            .iter()
+            .map(|(user, info)| json!({
+                "id": user,
+                "name": participants.name(user),
+                "seen": info.seen.len(),
+                "checks_passed": info.checks_passed,
+                "checks_failed": info.checks_failed,
+                "condition": study.condition(info).map(|c| c.name),
+                "excluded": info.excluded()
+            }))
+            .collect::<Vec<_>>();
+
+        let page = surface_page(study, &query);
+        let ranges = study.config.questionnaire.iter()
+            .map(|q| {
+                let range = query.experimenter.get(q.short).cloned().unwrap_or((None, None));
+                json!({ "question": q.short, "min": range.0, "max": range.1 })
             })
             .collect::<Vec<_>>();
 
+        let pages = page["pages"].as_u64().unwrap() as usize;
+
         Ok(Template::render("list", json!({
-            "surfaces": surfaces,
+            "admin": admin.name,
+            "study": study.config.id,
+            "title": study.config.title,
+            "page": page,
+            "prev": if query.page > 1 { Some(query.to_query(query.page - 1)) } else { None },
+            "next": if query.page < pages { Some(query.to_query(query.page + 1)) } else { None },
+            "reported": query.reported.map(|r| r.to_string()),
+            "query": query,
+            "questions": study.config.questionnaire.iter().map(|q| q.short).collect::<Vec<_>>(),
+            "ranges": ranges,
+            "raters": raters,
             "time": elapsed(start)
         })))
     }
 }
 
+handle_admin! {
+    #[get("/study/<id>/list.json")]
+    pub fn list_json/list_json_login(admin: Admin, studies: State<Studies>, id: String, query: ListQuery) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(Content(ContentType::JSON, ::serde_json::to_string(&surface_page(study, &query)).unwrap()))
+    }
+}
+
+handle_admin! {
+    #[post("/study/<id>/exclusion", data="<form>")]
+    pub fn exclusion/exclusion_login(admin: Admin, studies: State<Studies>, id: String, form: Form<Exclusion>) -> Redirect {
+        let study = Study::find(&studies, &id)?;
+        let Exclusion { date, flow, num, action } = form.into_inner();
+        let exclude = Exclusion::exclude(&action)?;
+
+        study.reports.lock().unwrap().entry((date, flow, num)).or_insert_with(Default::default).exclude = exclude;
+
+        let mut file = study.append(settings::EXCLUSIONS)?;
+        writeln!(&mut file, "{},{},{},{}", date, flow, num, action)?;
+
+        Ok(Redirect::to(&format!("/study/{}/list", id)))
+    }
+}
+
 handle_login! {
-    #[get("/<date>/<flow>/<idx>")]
-    pub fn episode/episode_login(user: User, users: State<ActiveUsers>, date: Datestamp, flow: Option<FlowType>, idx: u32) -> Template {
+    #[get("/study/<id>/<date>/<flow>/<idx>")]
+    pub fn episode/episode_login(user: User, studies: State<Studies>, id: String, date: Datestamp, flow: Option<FlowType>, idx: u32) -> Template {
+        let study = Study::find(&studies, &id)?;
         let flow = flow.ok_or(ErrorKind::BadParam("invalid flow type"))?;
+        let (data, _) = load_episode(date, flow, idx)?;
 
-        let data = {
-            let mut data = None;
-            for dir in settings::DATADIRS {
-                let mut path = PathBuf::from(dir);
-                path.push(format!("{}", date.0));
-                path.push(format!("{}", flow));
-                path.push(format!("{}", idx));
-                path.push(format!("{}.flow", flow));
-
-                data = match SurfaceData::from_flow_file(date, flow, idx, &path) {
-                    Ok(d) => Some(d),
-                    Err(e) => match *e.kind() {
-                        ErrorKind::Io(ref ioe) => match ioe.kind() {
-                            io::ErrorKind::NotFound => continue,
-                            _ => break
-                        },
-                        _ => break
-                    }
-                };
-            }
-
-            data.ok_or(io::Error::new(io::ErrorKind::NotFound, "episode not found in any datadir"))?
+        let mut users = study.users.lock().unwrap();
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        // opening a trial directly doesn't skip onboarding or the practice phase
+        if let Some(page) = onboarding_page(study, &user, user_info, "") {
+            return Ok(page);
+        }
+        if user_info.practiced < study.practice.len() {
+            return Ok(render_practice(study, &user, user_info, ""));
+        }
+        let attention = match user_info.check {
+            Some(ref check) if check.is_for(date, flow, idx) => check.attention(),
+            _ => None
         };
-
-        let mut users = users.lock().unwrap();
-        let user_info = users.entry(user.clone()).or_insert_with(Default::default);
         let rate_error = if user_info.rate_error {
             user_info.rate_error = false;
             "All ratings are required"
@@ -122,10 +899,23 @@ handle_login! {
             "At least one reason is required"
         } else { "" };
 
-        Ok(Template::render("episode",
+        let (template, categories, describe) = match study.config.mode {
+            Mode::Labeling { categories, describe } => ("label", categories, describe),
+            _ => (study.condition(user_info).and_then(|c| c.template).unwrap_or("episode"), &[][..], false)
+        };
+        let (condition, flags) = condition_context(study, Some(user_info));
+
+        Ok(Template::render(template,
                             json!({
+                                "condition": condition,
+                                "flags": flags,
+                                "categories": categories,
+                                "describe": describe,
                                 "rate_error": rate_error,
                                 "report_error": report_error,
+                                "attention": attention,
+                                "study": study.config.id,
+                                "questions": questions_with_ratings(study, &data),
                                 "user": user,
                                 "surface": data,
                                 "date": date.0,
@@ -135,88 +925,837 @@ handle_login! {
     }
 }
 
+/// Make sure the user may record practice trials (onboarding comes first)
+fn ensure_onboarded(info: &UserInfo) -> Result<()> {
+    if let Some(step) = onboarding_step(info) {
+        Err(ErrorKind::PendingStep(step))?;
+    }
+    Ok(())
+}
+
+/// Make sure the user may record main-phase trials (onboarding and the practice trials, if any, come first)
+pub fn ensure_main_phase(study: &Study, info: &UserInfo) -> Result<()> {
+    ensure_onboarded(info)?;
+    if info.practiced < study.practice.len() {
+        Err(ErrorKind::PendingStep("practice"))?;
+    }
+    Ok(())
+}
+
+/// Pick the next main-phase surface of a Likert or labeling study for a user (`None` if there are
+/// no surfaces left for them)
+///
+/// If the trial is an attention check, it is stored in the user's info.
+pub fn choose_surface(study: &Study, user: &User) -> Result<Option<(Datestamp, FlowType, u32)>> {
+    let sampling = &study.config.sampling;
+    let surfaces = &study.surfaces;
+    let mut users = study.users.lock().unwrap();
+    let reports = study.reports.lock().unwrap();
+    ensure_order(study, &mut users, user)?;
+    let user_info = users.get_mut(&user.id).unwrap();
+    user_info.check = None;
+
+    let (idx, mut rng) = {
+        let seen = &user_info.seen;
+        let order = user_info.order.as_ref().unwrap();
+        let mut available = order.surfaces.iter()
+                                          .cloned()
+                                          .filter(|&i| {
+                                              let key = (surfaces[i].date, surfaces[i].flow, surfaces[i].num);
+                                              !seen.contains(&key) && !reports.get(&key).map_or(false, |t| t.excluded(sampling))
+                                          });
+        let idx = match sampling.adaptive {
+            Some(ref adaptive) => {
+                let moments = study.moments.lock().unwrap();
+                adaptive.choose(&available.collect::<Vec<_>>(), |i| {
+                    moments.get(&(surfaces[i].date, surfaces[i].flow, surfaces[i].num)).map(|m| &m[..])
+                })
+            }
+            None => available.next()
+        };
+        let idx = match idx {
+            Some(idx) => idx,
+            None => return Ok(None)
+        };
+        (idx, order.rng_at(seen.len()))
+    };
+
+    let likert = match study.config.mode { Mode::Likert => true, _ => false };
+    let checking = likert && rng.gen::<f64>() < sampling.check_rate;
+    // each gold surface is shown to a user at most once
+    let unseen_gold = sampling.gold.iter()
+                                   .filter(|g| !user_info.seen.contains(&(g.date, g.flow, g.num)))
+                                   .collect::<Vec<_>>();
+    if checking && !unseen_gold.is_empty() && rng.gen::<f64>() < sampling.gold_rate {
+        let gold = *rng.choose(&unseen_gold).unwrap();
+        let (date, flow, num) = (gold.date, gold.flow, gold.num);
+        user_info.check = Some(Check::Gold { date, flow, num });
+        Ok(Some((date, flow, num)))
+    } else {
+        let (date, flow, num) = (surfaces[idx].date, surfaces[idx].flow, surfaces[idx].num);
+        if checking {
+            user_info.check = Some(Check::Instructed { date, flow, num, answer: Likert(rng.gen_range(1, 6)) });
+        }
+        Ok(Some((date, flow, num)))
+    }
+}
+
+handle_login! {
+    #[get("/study/<id>/random")]
+    pub fn random/random_login(user: User, studies: State<Studies>, id: String) -> Template {
+        let (date, flow, num) = {
+            let study = Study::find(&studies, &id)?;
+            {
+                let mut users = study.users.lock().unwrap();
+                ensure_order(study, &mut users, &user)?;
+                let user_info = users.get_mut(&user.id).unwrap();
+                if let Some(page) = onboarding_page(study, &user, user_info, "") {
+                    return Ok(page);
+                }
+            }
+            if let Some(page) = quota_page(study, &user)? {
+                return Ok(page);
+            }
+            {
+                let users = study.users.lock().unwrap();
+                let user_info = &users[&user.id];
+                if user_info.practiced < study.practice.len() {
+                    return Ok(render_practice(study, &user, user_info, ""));
+                }
+            }
+            match study.config.mode {
+                Mode::Likert | Mode::Labeling { .. } => {}
+                Mode::Pairwise => return Ok(pair_trial(study, &user)?),
+                Mode::Ranking { size } => return Ok(ranking_trial(study, &user, size)?),
+            }
+
+            match choose_surface(study, &user)? {
+                Some(key) => key,
+                None => return Ok(finished_page(study, &user)?)
+            }
+        };
+
+        Ok(episode(user, studies, id, date, Some(flow), num)?)
+    }
+}
+
+/// Record a Likert rating (and grade it if it is the user's pending attention check)
+///
+/// Answers to a gold check only go to the checks file, so they don't count as ratings of the surface.
+/// Returns `false` without recording anything if some question is unanswered.
+pub fn record_rating(study: &Study, user: &User, surface: SurfaceData) -> Result<bool> {
+    let SurfaceData { date, flow, num, mut ratings } = surface;
+    let attention = ratings.remove("attention");
+    let answers = match study.config.questionnaire.iter()
+                                                  .map(|q| ratings.get(q.short).map(|r| r.0.to_string()))
+                                                  .collect::<Option<Vec<_>>>() {
+        Some(answers) => answers,
+        None => return Ok(false)
+    };
+
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    ensure_main_phase(study, user_info)?;
+    let check = if user_info.check.as_ref().map_or(false, |c| c.is_for(date, flow, num)) {
+        user_info.check.take()
+    } else {
+        None
+    };
+    let gold = match check { Some(Check::Gold { .. }) => true, _ => false };
+    user_info.seen.push((date, flow, num));
+    log_trial(study, user, user_info, date, flow, num, if gold { "gold" } else { "rating" })?;
+
+    if !gold {
+        study.reports.lock().unwrap().entry((date, flow, num)).or_insert_with(Default::default).views += 1;
+        let likerts = study.config.questionnaire.iter().map(|q| ratings[q.short]).collect::<Vec<_>>();
+        study::tally_ratings(&mut study.moments.lock().unwrap(), (date, flow, num), &likerts);
+
+        let mut file = study.append(settings::RATINGS)?;
+        writeln!(&mut file, "{},{},{},{},{}{}", user.id, date, flow, num, answers.join(","), study.condition_column(user_info))?;
+    }
+
+    if let Some(check) = check {
+        match check.passed(study.config.sampling.gold, &ratings, attention) {
+            Some(passed) => {
+                if passed {
+                    user_info.checks_passed += 1;
+                } else {
+                    user_info.checks_failed += 1;
+                }
+
+                let mut file = study.append(settings::CHECKS)?;
+                writeln!(&mut file, "{},{},{},{},{},{},{}{}", user.id, date, flow, num, check.kind(), passed,
+                         answers.join(","), study.condition_column(user_info))?;
+            }
+            None => println!("WARNING: {}/{}/{} is not a gold surface any more, ignoring the check for {}", date, flow, num, user.id)
+        }
+    }
+
+    Ok(true)
+}
+
+handle_login! {
+    #[post("/study/<id>/rate", data="<form>")]
+    fn rate/rate_login(user: User, studies: State<Studies>, id: String, form: Form<SurfaceData>) -> Template {
+        let surface = form.into_inner();
+        let (date, flow, num) = (surface.date, surface.flow, surface.num);
+
+        let complete = {
+            let study = Study::find(&studies, &id)?;
+            let complete = record_rating(study, &user, surface)?;
+            if !complete {
+                study.users.lock().unwrap().entry(user.id.clone()).or_insert_with(Default::default).rate_error = true;
+            }
+            complete
+        };
+
+        if complete {
+            Ok(random(user, studies, id)?)
+        } else {
+            Ok(episode(user, studies, id, date, Some(flow), num)?)
+        }
+    }
+}
+
+/// Record the answers to an onboarding step
+///
+/// Returns the error to show the user if the answers are incomplete.
+pub fn record_onboarding(study: &Study, user: &User, info: &mut UserInfo, step: &str, answers: &HashMap<String, String>) -> Result<Option<&'static str>> {
+    Ok(match step {
+        "consent" => {
+            if answers.contains_key("agree") && answers.get("version").map(|v| &v[..]) == Some(settings::CONSENT_VERSION) {
+                let time = timestamp();
+                log_onboarding(study, user, info, "consent", settings::CONSENT_VERSION)?;
+                info.consent = Some((settings::CONSENT_VERSION.to_owned(), time));
+                None
+            } else {
+                Some("You must agree to the consent form to take part")
+            }
+        }
+
+        "demographics" => {
+            let chosen = settings::DEMOGRAPHICS.iter()
+                                               .map(|d| match answers.get(d.short) {
+                                                   Some(a) if d.options.contains(&a.as_str()) => Some((d.short, a.clone())),
+                                                   _ => None
+                                               })
+                                               .collect::<Option<Vec<_>>>();
+            match chosen {
+                Some(chosen) => {
+                    for (short, answer) in chosen {
+                        log_onboarding(study, user, info, short, &answer)?;
+                        info.demographics.insert(short.to_owned(), answer);
+                    }
+                    None
+                }
+                None => Some("Please answer every question")
+            }
+        }
+
+        "instructions" => {
+            log_onboarding(study, user, info, "instructions", "read")?;
+            info.instructed = true;
+            None
+        }
+
+        _ => Err(ErrorKind::BadParam("unknown onboarding step"))?
+    })
+}
+
 handle_login! {
-    #[get("/random")]
-    pub fn random/random_login(user: User, users: State<ActiveUsers>, surfaces: State<Vec<SurfaceData>>) -> Template {
+    #[post("/study/<id>/onboarding/<step>", data="<form>")]
+    fn onboarding/onboarding_login(user: User, studies: State<Studies>, id: String, step: String, form: Form<Answers>) -> Template {
+        let Answers(answers) = form.into_inner();
 
-        let mut rng = rand::thread_rng();
-        let range = Range::new(0, surfaces.len());
-        let (mut date, mut flow, mut num);
         {
-            let mut users = users.lock().unwrap();
-            let user_info = users.entry(user.clone()).or_insert_with(Default::default);
-            loop {
-                let idx = range.ind_sample(&mut rng);
-                date = surfaces[idx].date;
-                flow = surfaces[idx].flow;
-                num = surfaces[idx].num;
-                if !user_info.seen.contains(&(date, flow, num)) {
-                    break;
+            let study = Study::find(&studies, &id)?;
+            let mut users = study.users.lock().unwrap();
+            let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+
+            if let Some(error) = record_onboarding(study, &user, user_info, &step, &answers)? {
+                if let Some(page) = onboarding_page(study, &user, user_info, error) {
+                    return Ok(page);
                 }
             }
         }
 
-        Ok(episode(user, users, date, Some(flow), num)?)
+        Ok(random(user, studies, id)?)
     }
 }
 
+/// Record a rating of the user's current practice surface
+///
+/// Returns the experimenter's rating of each question next to the user's, or `None` without
+/// recording anything if some question is unanswered.
+pub fn record_practice(study: &Study, user: &User, info: &mut UserInfo, surface: SurfaceData) -> Result<Option<Vec<::serde_json::Value>>> {
+    let SurfaceData { date, flow, num, ratings } = surface;
+    ensure_onboarded(info)?;
+    let expected = match study.practice.get(info.practiced) {
+        Some(s) if (s.date, s.flow, s.num) == (date, flow, num) => s,
+        _ => Err(ErrorKind::BadParam("not the current practice surface"))?
+    };
+
+    let answers = study.config.questionnaire.iter()
+                                            .map(|q| ratings.get(q.short).map(|r| r.0))
+                                            .collect::<Option<Vec<_>>>();
+    let answers = match answers {
+        Some(answers) => answers,
+        None => return Ok(None)
+    };
+
+    let mut file = study.append(settings::PRACTICE)?;
+    writeln!(&mut file, "{},{},{},{},{}{}", user.id, date, flow, num,
+             answers.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(","), study.condition_column(info))?;
+    log_trial(study, user, info, date, flow, num, "practice")?;
+    info.practiced += 1;
+
+    Ok(Some(study.config.questionnaire.iter()
+        .zip(answers)
+        .map(|(q, answer)| json!({
+            "question": q,
+            "answer": answer,
+            "expected": expected.ratings.get(q.short)
+        }))
+        .collect()))
+}
+
 handle_login! {
-    #[post("/rate", data="<form>")]
-    fn rate/rate_login(user: User, users: State<ActiveUsers>, surfaces: State<Vec<SurfaceData>>, form: Form<SurfaceData>) -> Template {
-        let SurfaceData { date, flow, num, ratings } = form.into_inner();
-        if_chain!([let Some(warm) => ratings.get("warm"),
-                   let Some(hard) => ratings.get("hard"),
-                   let Some(rough) => ratings.get("rough"),
-                   let Some(sticky) => ratings.get("sticky")] {
-            {
-                let mut users = users.lock().unwrap();
-                let user_info = users.entry(user.clone()).or_insert_with(Default::default);
-                user_info.seen.push((date, flow, num));
+    #[post("/study/<id>/practice", data="<form>")]
+    fn practice/practice_login(user: User, studies: State<Studies>, id: String, form: Form<SurfaceData>) -> Template {
+        let study = Study::find(&studies, &id)?;
+
+        let mut users = study.users.lock().unwrap();
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        let feedback = match record_practice(study, &user, user_info, form.into_inner())? {
+            Some(feedback) => feedback,
+            None => return Ok(render_practice(study, &user, user_info, "All ratings are required"))
+        };
+
+        Ok(Template::render("feedback",
+                            json!({
+                                "study": study.config.id,
+                                "user": user,
+                                "surface": study.practice[user_info.practiced - 1],
+                                "feedback": feedback,
+                                "remaining": study.practice.len() - user_info.practiced
+                            })))
+    }
+}
+
+/// Record a label of a main-phase surface
+///
+/// Returns `false` without recording anything if the category is missing or not one of the study's.
+pub fn record_label(study: &Study, user: &User, label: Label) -> Result<bool> {
+    let Label { date, flow, num, category, description } = label;
+    let categories = match study.config.mode {
+        Mode::Labeling { categories, .. } => categories,
+        _ => Err(ErrorKind::BadParam("study does not collect labels"))?
+    };
+
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    ensure_main_phase(study, user_info)?;
+    match category {
+        Some(ref category) if category == DONT_KNOW || categories.contains(&category.as_str()) => {
+            user_info.seen.push((date, flow, num));
+            log_trial(study, user, user_info, date, flow, num, "label")?;
+            study.reports.lock().unwrap().entry((date, flow, num)).or_insert_with(Default::default).views += 1;
+
+            let mut record = vec![user.id.clone(), date.to_string(), flow.to_string(), num.to_string(),
+                                  category.clone(), description.unwrap_or_default()];
+            study.push_condition(user_info, &mut record);
+            let mut csv = csv::Writer::from_writer(study.append(settings::LABELS)?);
+            csv.write_record(&record)?;
+            Ok(true)
+        }
+        _ => Ok(false)
+    }
+}
+
+handle_login! {
+    #[post("/study/<id>/label", data="<form>")]
+    fn label/label_login(user: User, studies: State<Studies>, id: String, form: Form<Label>) -> Template {
+        let label = form.into_inner();
+        let (date, flow, num) = (label.date, label.flow, label.num);
+
+        let complete = {
+            let study = Study::find(&studies, &id)?;
+            let complete = record_label(study, &user, label)?;
+            if !complete {
+                study.users.lock().unwrap().entry(user.id.clone()).or_insert_with(Default::default).rate_error = true;
             }
+            complete
+        };
 
-            let mut file = OpenOptions::new().append(true).open(settings::RATINGS)?;
-            writeln!(&mut file, "{},{},{},{},{},{},{},{}", user.name, date, flow, num, warm.0, hard.0, rough.0, sticky.0)?;
+        if complete {
+            Ok(random(user, studies, id)?)
+        } else {
+            Ok(episode(user, studies, id, date, Some(flow), num)?)
+        }
+    }
+}
+
+/// Record a pairwise comparison of the pair that was served to the user
+///
+/// Returns `false` without recording anything if some question is unanswered.
+pub fn record_comparison(study: &Study, user: &User, comparison: Comparison) -> Result<bool> {
+    let Comparison { a, b, choices } = comparison;
+    let (ia, ib) = match (study.index_of(a), study.index_of(b)) {
+        (Some(ia), Some(ib)) if ia != ib => (ia, ib),
+        _ => Err(ErrorKind::BadParam("invalid pair of surfaces"))?
+    };
+    let pair = if ia < ib { (ia, ib) } else { (ib, ia) };
+    {
+        let users = study.users.lock().unwrap();
+        let info = users.get(&user.id);
+        ensure_main_phase(study, info.unwrap_or(&UserInfo::default()))?;
+        // only the pair that was actually served may be compared
+        if info.and_then(|info| info.pair) != Some(pair) {
+            Err::<(), _>(ErrorKind::BadParam("not the pair that was shown"))?;
+        }
+    }
+
+    let winners = match study.config.questionnaire.iter()
+                                                  .map(|q| choices.get(q.short).map(|&side| (q.short, side)))
+                                                  .collect::<Option<Vec<_>>>() {
+        Some(winners) => winners,
+        None => return Ok(false)
+    };
+
+    let (new, condition) = {
+        let mut users = study.users.lock().unwrap();
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        user_info.pair = None;
+        let new = user_info.compared.insert(pair);
+        if new {
+            *study.comparisons.lock().unwrap().entry(pair).or_insert(0) += 1;
+        }
+        (new, study.condition_column(user_info))
+    };
+
+    // a resubmitted form must not count the same judgment twice
+    if new {
+        let mut file = study.append(settings::PAIRS)?;
+        for (question, side) in winners {
+            writeln!(&mut file, "{},{},{},{},{},{},{},{},{}{}", user.id, a.0, a.1, a.2, b.0, b.1, b.2, question, side, condition)?;
+        }
+    }
+    Ok(true)
+}
+
+handle_login! {
+    #[post("/study/<id>/compare", data="<form>")]
+    fn compare/compare_login(user: User, studies: State<Studies>, id: String, form: Form<Comparison>) -> Template {
+        let comparison = form.into_inner();
+        let (a, b) = (comparison.a, comparison.b);
+        let study = Study::find(&studies, &id)?;
 
-            Ok(random(user, users, surfaces)?)
+        if record_comparison(study, &user, comparison)? {
+            pair_trial(study, &user)
         } else {
-            {
-                let mut users = users.lock().unwrap();
-                let user_info = users.entry(user.clone()).or_insert_with(Default::default);
-                user_info.rate_error = true;
-            }
-            Ok(episode(user, users, date, Some(flow), num)?)
-        })
+            let (ia, ib) = (study.index_of(a).unwrap(), study.index_of(b).unwrap());
+            Ok(render_pair(study, &user, ia, ib, "All questions are required"))
+        }
+    }
+}
+
+/// Record a ranking of the surfaces that were served to the user
+///
+/// Returns `false` without recording anything if the ranking is incomplete.
+pub fn record_ranking(study: &Study, user: &User, form: &RankingForm) -> Result<bool> {
+    let question = study.config.questionnaire.iter()
+                                             .find(|q| q.short == form.question)
+                                             .ok_or(ErrorKind::BadParam("unknown question"))?;
+    let shown = form.surfaces()?;
+    let shown_idx = shown.iter()
+                         .map(|&key| study.index_of(key))
+                         .collect::<Option<Vec<_>>>()
+                         .ok_or(ErrorKind::BadParam("unknown surface"))?;
+    ensure_main_phase(study, study.users.lock().unwrap().get(&user.id).unwrap_or(&UserInfo::default()))?;
+
+    // only the surfaces and question that were actually served may be ranked (once)
+    {
+        let users = study.users.lock().unwrap();
+        let served = users.get(&user.id).and_then(|info| info.ranking.as_ref());
+        let matches = served.map_or(false, |&(ref served, short)| {
+            let mut served = served.clone();
+            let mut submitted = shown_idx.clone();
+            served.sort();
+            submitted.sort();
+            short == question.short && served == submitted
+        });
+        if !matches {
+            Err::<(), _>(ErrorKind::BadParam("not the ranking trial that was shown"))?;
+        }
+    }
+
+    let order = match form.order(shown.len()) {
+        Some(order) => order,
+        None => return Ok(false)
+    };
+    let (trial, condition) = {
+        let mut users = study.users.lock().unwrap();
+        let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+        user_info.ranking = None;
+        user_info.rankings += 1;
+        (user_info.rankings - 1, study.condition_column(user_info))
+    };
+
+    let mut file = study.append(settings::RANKINGS)?;
+    for (rank, &pos) in order.iter().enumerate() {
+        let (date, flow, num) = shown[pos];
+        writeln!(&mut file, "{},{},{},{},{},{},{},{}{}", user.id, trial, question.short, date, flow, num, pos, rank + 1, condition)?;
     }
+    Ok(true)
 }
 
 handle_login! {
-    #[post("/report", data="<report>")]
-    fn report/report_login(user: User, users: State<ActiveUsers>, surfaces: State<Vec<SurfaceData>>, reports: State<Reports>, report: Form<Report>) -> Template {
-        let Report { date, flow, num, dark, bright, blurry, grainy } = report.into_inner();
+    #[post("/study/<id>/rank", data="<form>")]
+    fn rank/rank_login(user: User, studies: State<Studies>, id: String, form: Form<RankingForm>) -> Template {
+        let study = Study::find(&studies, &id)?;
+        let form = form.into_inner();
+        let size = match study.config.mode {
+            Mode::Ranking { size } => size,
+            _ => Err(ErrorKind::BadParam("study does not collect rankings"))?
+        };
 
-        if dark || bright || blurry || grainy {
-            {
-                let mut users = users.lock().unwrap();
-                let user_info = users.entry(user.clone()).or_insert_with(Default::default);
-                user_info.seen.push((date, flow, num));
-            }
+        if record_ranking(study, &user, &form)? {
+            ranking_trial(study, &user, size)
+        } else {
+            let question = study.config.questionnaire.iter().find(|q| q.short == form.question).unwrap();
+            let shown = form.surfaces()?.into_iter().map(|key| study.index_of(key).unwrap()).collect::<Vec<_>>();
+            Ok(render_ranking(study, &user, &shown, question, "Please rank all of the surfaces"))
+        }
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/implied_pairs.csv")]
+    pub fn export_implied_pairs/export_implied_pairs_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::implied_pairs(study)?))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/label_agreement.csv")]
+    pub fn export_label_agreement/export_label_agreement_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::label_agreement(study)?))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/long.csv")]
+    pub fn export_long/export_long_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::long(study)?))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/long.dictionary.json")]
+    pub fn export_long_dictionary/export_long_dictionary_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(Content(ContentType::JSON, export::long_dictionary(study)))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/wide.csv")]
+    pub fn export_wide/export_wide_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::wide(study)?))
+    }
+}
 
-            reports.lock().unwrap().insert((date, flow, num));
+handle_admin! {
+    #[get("/study/<id>/export/wide.dictionary.json")]
+    pub fn export_wide_dictionary/export_wide_dictionary_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(Content(ContentType::JSON, export::wide_dictionary(study)))
+    }
+}
 
-            let mut file = OpenOptions::new().append(true).open(settings::REPORTS)?;
-            writeln!(&mut file, "{},{},{},{},{},{},{},{}", user.name, date, flow, num, dark, bright, blurry, grainy)?;
+handle_admin! {
+    #[get("/study/<id>/export/dataset?<options>")]
+    pub fn export_dataset/export_dataset_login(admin: Admin, studies: State<Studies>, id: String, options: DatasetOptions) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        match &options.format[..] {
+            "csv" => Ok(csv_response(export::dataset_csv(study, &options)?)),
+            "jsonl" => Ok(Content(ContentType::new("application", "x-ndjson"), export::dataset_jsonl(study, &options)?)),
+            _ => Err(ErrorKind::BadParam("unknown dataset format"))?
+        }
+    }
+}
 
-            Ok(random(user, users, surfaces)?)
+handle_admin! {
+    #[get("/study/<id>/dashboard")]
+    pub fn dashboard/dashboard_login(admin: Admin, studies: State<Studies>, id: String) -> Template {
+        let start = SystemTime::now();
+        let study = Study::find(&studies, &id)?;
+        Ok(Template::render("dashboard", json!({
+            "admin": admin.name,
+            "study": study.config.id,
+            "title": study.config.title,
+            "dashboard": dashboard::dashboard(study)?,
+            "time": elapsed(start)
+        })))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/diagnostics")]
+    pub fn diagnostics/diagnostics_login(admin: Admin, studies: State<Studies>, id: String) -> Template {
+        let study = Study::find(&studies, &id)?;
+        let excluded = study.excluded_users();
+        let raters = diagnostics::diagnostics(study)?
+            .into_iter()
+            .map(|d| json!({
+                "id": d.id,
+                "excluded": excluded.contains(&d.id),
+                "trials": d.trials,
+                "variance": decimal(d.variance),
+                "identical": decimal(d.identical),
+                "latency": d.latency.map_or("n/a".to_owned(), decimal),
+                "consensus": decimal(d.consensus),
+                "gold": d.gold.map_or("n/a".to_owned(), |g| format!("{} ({} checks)", decimal(g), d.gold_checks)),
+                "flags": d.flags
+            }))
+            .collect::<Vec<_>>();
+        Ok(Template::render("diagnostics", json!({
+            "admin": admin.name,
+            "study": study.config.id,
+            "title": study.config.title,
+            "thresholds": {
+                "min_trials": settings::DIAGNOSTICS.min_trials,
+                "min_variance": settings::DIAGNOSTICS.min_variance,
+                "max_identical": settings::DIAGNOSTICS.max_identical,
+                "min_latency": settings::DIAGNOSTICS.min_latency,
+                "min_consensus": settings::DIAGNOSTICS.min_consensus,
+                "min_gold": settings::DIAGNOSTICS.min_gold
+            },
+            "raters": raters
+        })))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/rater_exclusions.csv")]
+    pub fn export_rater_exclusions/export_rater_exclusions_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(diagnostics::exclusion_list(study)?))
+    }
+}
+
+handle_admin! {
+    #[post("/study/<id>/diagnostics/exclude")]
+    pub fn save_rater_exclusions/save_rater_exclusions_login(admin: Admin, studies: State<Studies>, id: String) -> Redirect {
+        let study = Study::find(&studies, &id)?;
+        let n = diagnostics::save_exclusion_list(study)?;
+        println!("Admin {} excluded {} flagged raters from {}", admin.name, n, id);
+        Ok(Redirect::to(&format!("/study/{}/diagnostics", id)))
+    }
+}
+
+handle_admin! {
+    #[get("/admin/reliability")]
+    pub fn admin_reliability/admin_reliability_login(admin: Admin, studies: State<Studies>) -> Template {
+        let start = SystemTime::now();
+        let format = |e: &Estimate| if e.value.is_nan() {
+            "n/a".to_owned()
         } else {
-            {
-                let mut users = users.lock().unwrap();
-                let user_info = users.entry(user.clone()).or_insert_with(Default::default);
-                user_info.report_error = true;
+            format!("{:.3} [{:.3}, {:.3}]", e.value, e.low, e.high)
+        };
+        let results = reliability::reliability(&studies)?
+            .into_iter()
+            .map(|r| json!({
+                "study": r.study,
+                "flow": r.flow.map_or("all".to_owned(), |f| f.to_string()),
+                "question": r.question,
+                "surfaces": r.surfaces,
+                "ratings": r.ratings,
+                "raters": r.raters,
+                "alpha": format(&r.alpha),
+                "icc": format(&r.icc),
+                "icc_raters": r.icc_raters,
+                "icc_surfaces": r.icc_surfaces,
+                "spearman": format(&r.spearman),
+                "spearman_pairs": r.spearman_pairs
+            }))
+            .collect::<Vec<_>>();
+        Ok(Template::render("reliability", json!({
+            "admin": admin.name,
+            "results": results,
+            "samples": settings::BOOTSTRAP_SAMPLES,
+            "confidence": settings::CONFIDENCE * 100.0,
+            "time": elapsed(start)
+        })))
+    }
+}
+
+/// Format a statistic for display ("n/a" if it is undefined)
+fn decimal(x: f64) -> String {
+    if x.is_finite() {
+        format!("{:.3}", x)
+    } else {
+        "n/a".to_owned()
+    }
+}
+
+handle_admin! {
+    #[get("/admin/agreement")]
+    pub fn admin_agreement/admin_agreement_login(admin: Admin, studies: State<Studies>) -> Template {
+        let start = SystemTime::now();
+        let results = agreement::agreement(&studies)?
+            .into_iter()
+            .map(|a| json!({
+                "study": a.study,
+                "flow": a.flow.map_or("all".to_owned(), |f| f.to_string()),
+                "question": a.question,
+                "surfaces": a.surfaces,
+                "pearson": decimal(a.pearson),
+                "spearman": decimal(a.spearman),
+                "mad": decimal(a.mad),
+                "bias": decimal(a.bland_altman.bias),
+                "sd": decimal(a.bland_altman.sd),
+                "lower": decimal(a.bland_altman.lower),
+                "upper": decimal(a.bland_altman.upper),
+                "confusion": a.confusion
+            }))
+            .collect::<Vec<_>>();
+        Ok(Template::render("agreement", json!({
+            "admin": admin.name,
+            "results": results,
+            "time": elapsed(start)
+        })))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/agreement/<flow>")]
+    pub fn admin_agreement_surfaces/admin_agreement_surfaces_login(admin: Admin, studies: State<Studies>, id: String, flow: String) -> Template {
+        let study = Study::find(&studies, &id)?;
+        let flow = if flow == "all" {
+            None
+        } else {
+            Some(flow.parse::<FlowType>().map_err(|e| ErrorKind::BadParam(e))?)
+        };
+
+        // rows come grouped by surface
+        let mut surfaces = Vec::<::serde_json::Value>::new();
+        let mut last = None;
+        for row in agreement::surfaces(study)? {
+            if flow.map_or(false, |f| f != row.flow) {
+                continue;
+            }
+            let key = (row.date, row.flow, row.num);
+            if last != Some(key) {
+                last = Some(key);
+                surfaces.push(json!({
+                    "date": row.date.0,
+                    "flow": row.flow.to_string(),
+                    "number": row.num,
+                    "ratings": {}
+                }));
             }
-            Ok(episode(user, users, date, Some(flow), num)?)
+            surfaces.last_mut().unwrap()["ratings"].as_object_mut().unwrap()
+                .insert(row.question.into(), json!({
+                    "experimenter": row.experimenter,
+                    "crowd": decimal(row.crowd),
+                    "n": row.ratings,
+                    "difference": decimal(row.difference())
+                }));
         }
 
+        Ok(Template::render("agreement_surfaces", json!({
+            "admin": admin.name,
+            "study": study.config.id,
+            "title": study.config.title,
+            "flow": flow.map_or("all".to_owned(), |f| f.to_string()),
+            "questions": study.config.questionnaire,
+            "surfaces": surfaces
+        })))
     }
 }
 
+handle_admin! {
+    #[get("/admin/agreement.csv")]
+    pub fn export_agreement/export_agreement_login(admin: Admin, studies: State<Studies>) -> Content<String> {
+        Ok(csv_response(export::agreement(&studies)?))
+    }
+}
 
+handle_admin! {
+    #[get("/study/<id>/export/surface_agreement.csv")]
+    pub fn export_surface_agreement/export_surface_agreement_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::surface_agreement(study)?))
+    }
+}
+
+handle_admin! {
+    #[get("/admin/reliability.csv")]
+    pub fn export_reliability/export_reliability_login(admin: Admin, studies: State<Studies>) -> Content<String> {
+        Ok(csv_response(export::reliability(&studies)?))
+    }
+}
+
+handle_admin! {
+    #[get("/study/<id>/export/bradley_terry.csv")]
+    pub fn export_bradley_terry/export_bradley_terry_login(admin: Admin, studies: State<Studies>, id: String) -> Content<String> {
+        let study = Study::find(&studies, &id)?;
+        Ok(csv_response(export::bradley_terry(study, &studies)?))
+    }
+}
+
+/// Record a report of a bad image
+///
+/// Returns `false` without recording anything if no reason is given.
+pub fn record_report(study: &Study, user: &User, report: Report) -> Result<bool> {
+    let Report { date, flow, num, dark, bright, blurry, grainy } = report;
+    if !(dark || bright || blurry || grainy) {
+        return Ok(false);
+    }
+
+    let mut users = study.users.lock().unwrap();
+    let user_info = users.entry(user.id.clone()).or_insert_with(Default::default);
+    ensure_main_phase(study, user_info)?;
+    user_info.seen.push((date, flow, num));
+    log_trial(study, user, user_info, date, flow, num, "report")?;
+
+    {
+        let mut reports = study.reports.lock().unwrap();
+        let tally = reports.entry((date, flow, num)).or_insert_with(Default::default);
+        tally.reports += 1;
+        tally.views += 1;
+    }
+
+    let mut file = study.append(settings::REPORTS)?;
+    writeln!(&mut file, "{},{},{},{},{},{},{},{}{}", user.id, date, flow, num, dark, bright, blurry, grainy,
+             study.condition_column(user_info))?;
+    Ok(true)
+}
+
+handle_login! {
+    #[post("/study/<id>/report", data="<report>")]
+    fn report/report_login(user: User, studies: State<Studies>, id: String, report: Form<Report>) -> Template {
+        let report = report.into_inner();
+        let (date, flow, num) = (report.date, report.flow, report.num);
+
+        let complete = {
+            let study = Study::find(&studies, &id)?;
+            let complete = record_report(study, &user, report)?;
+            if !complete {
+                study.users.lock().unwrap().entry(user.id.clone()).or_insert_with(Default::default).report_error = true;
+            }
+            complete
+        };
+
+        if complete {
+            Ok(random(user, studies, id)?)
+        } else {
+            Ok(episode(user, studies, id, date, Some(flow), num)?)
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use rand::{Rng, SeedableRng};
+use rand::isaac::Isaac64Rng;
+
+use analysis::Moments;
+use structs::{SurfaceData, Datestamp, FlowType};
+use study::StudyConfig;
+
+/// How trial orders are counterbalanced across users
+#[allow(dead_code)]
+pub enum Counterbalance {
+    /// Shuffle all surfaces together
+    None,
+    /// Group surfaces into blocks, shuffle within each block, and vary the block order between users
+    Blocks {
+        /// What the surfaces are grouped by
+        by: BlockKey,
+        /// Take block orders from a balanced Latin square instead of shuffling them
+        latin_square: bool
+    }
+}
+
+/// Grouping used for block counterbalancing
+#[allow(dead_code)]
+pub enum BlockKey {
+    FlowType,
+    Date
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Block {
+    FlowType(FlowType),
+    Date(Datestamp)
+}
+
+/// Adaptive sampling policy for Likert studies
+///
+/// Until every surface has `min_ratings` ratings, users follow their own trial order. After that
+/// each trial goes to the available surface whose widest confidence interval (over the
+/// questionnaire dimensions) is largest, and surfaces whose intervals are all narrower than
+/// `max_ci_width` are retired. Once every available surface is retired, users go back to their own
+/// trial order.
+pub struct Adaptive {
+    /// Ratings every surface gets before uncertainty is taken into account
+    pub min_ratings: u32,
+    /// Critical value of the confidence intervals (e.g. 1.96 for 95%)
+    pub z: f64,
+    /// Retire a surface once the confidence intervals of all dimensions are at most this wide
+    pub max_ci_width: f64,
+}
+
+impl Adaptive {
+    /// Width of the widest confidence interval over a surface's dimensions
+    pub fn width(&self, moments: &[Moments]) -> f64 {
+        moments.iter().map(|m| m.ci_width(self.z)).fold(0.0, f64::max)
+    }
+
+    /// Whether a surface has been rated precisely enough to stop sampling it
+    pub fn converged(&self, moments: &[Moments]) -> bool {
+        moments.first().map_or(false, |m| m.n >= self.min_ratings) && self.width(moments) <= self.max_ci_width
+    }
+
+    /// Choose the next surface among `candidates` (given in the user's trial order)
+    pub fn choose<'a, F: Fn(usize) -> Option<&'a [Moments]>>(&self, candidates: &[usize], moments: F) -> Option<usize> {
+        let count = |i| moments(i).and_then(|m| m.first()).map_or(0, |m| m.n);
+        if let Some(&i) = candidates.iter().find(|&&i| count(i) < self.min_ratings) {
+            return Some(i);
+        }
+
+        // a surface without ratings is as uncertain as can be
+        candidates.iter()
+                  .cloned()
+                  .map(|i| (i, moments(i)))
+                  .filter(|&(_, m)| !m.map_or(false, |m| self.converged(m)))
+                  .map(|(i, m)| (i, m.map_or(::std::f64::INFINITY, |m| self.width(m))))
+                  .fold(None, |best: Option<(usize, f64)>, (i, w)| match best {
+                      Some((_, bw)) if bw >= w => best,
+                      _ => Some((i, w))
+                  })
+                  .map(|(i, _)| i)
+                  .or_else(|| candidates.first().cloned())
+    }
+}
+
+/// Reproducible trial order for one user
+pub struct Order {
+    /// Seed derived from the study seed and the user ID
+    pub seed: u64,
+    /// Sequence number of this assignment (selects the Latin square row)
+    pub assignment: usize,
+    /// Indices into the surface list, in presentation order
+    pub surfaces: Vec<usize>
+}
+
+impl Order {
+    /// Generate the trial order for a user
+    ///
+    /// The surface list must be sorted so that the same seed always yields the same order.
+    pub fn new(study: &StudyConfig, user: &str, assignment: usize, surfaces: &[SurfaceData]) -> Self {
+        let seed = user_seed(study.sampling.seed, user);
+        let mut rng = Isaac64Rng::from_seed(&[seed][..]);
+
+        let order = match study.sampling.counterbalance {
+            Counterbalance::None => {
+                let mut order = (0..surfaces.len()).collect::<Vec<_>>();
+                rng.shuffle(&mut order);
+                order
+            }
+
+            Counterbalance::Blocks { ref by, latin_square } => {
+                let mut blocks = BTreeMap::new();
+                for (i, surf) in surfaces.iter().enumerate() {
+                    let block = match *by {
+                        BlockKey::FlowType => Block::FlowType(surf.flow),
+                        BlockKey::Date => Block::Date(surf.date),
+                    };
+                    blocks.entry(block).or_insert_with(Vec::new).push(i);
+                }
+                let mut blocks = blocks.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
+
+                let mut block_order = if latin_square {
+                    latin_square_row(blocks.len(), assignment)
+                } else {
+                    (0..blocks.len()).collect()
+                };
+                if !latin_square {
+                    rng.shuffle(&mut block_order);
+                }
+
+                let mut order = Vec::with_capacity(surfaces.len());
+                for b in block_order {
+                    rng.shuffle(&mut blocks[b]);
+                    order.extend_from_slice(&blocks[b]);
+                }
+                order
+            }
+        };
+
+        Order { seed, assignment, surfaces: order }
+    }
+
+    /// Position of a surface in this order
+    pub fn position(&self, surfaces: &[SurfaceData], date: Datestamp, flow: FlowType, num: u32) -> Option<usize> {
+        self.surfaces.iter().position(|&i| (surfaces[i].date, surfaces[i].flow, surfaces[i].num) == (date, flow, num))
+    }
+
+    /// Deterministic RNG for decisions made at a particular trial number (e.g. attention checks)
+    pub fn rng_at(&self, trial: usize) -> Isaac64Rng {
+        Isaac64Rng::from_seed(&[self.seed, trial as u64][..])
+    }
+
+    /// Deterministic RNG for the condition assignment (a stream separate from all trial numbers)
+    pub fn condition_rng(&self) -> Isaac64Rng {
+        Isaac64Rng::from_seed(&[self.seed, !0][..])
+    }
+}
+
+/// Derive a user's seed from the study seed and their ID (64-bit FNV-1a)
+pub fn user_seed(study_seed: u64, user: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325;
+    for b in study_seed.to_string().bytes().chain(Some(0)).chain(user.bytes()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Row of a balanced (Williams) Latin square of order `n`
+///
+/// For odd `n` there are `2n` rows (each row is followed by its mirror image), otherwise `n`.
+pub fn latin_square_row(n: usize, row: usize) -> Vec<usize> {
+    if n == 0 {
+        return vec![];
+    }
+
+    let rows = if n % 2 == 0 { n } else { 2 * n };
+    let r = row % rows;
+    let mut seq = (0..n).map(|j| {
+                            if j % 2 == 0 {
+                                (r + j / 2) % n
+                            } else {
+                                (r + n - (j + 1) / 2) % n
+                            }
+                        })
+                        .collect::<Vec<_>>();
+    if r >= n {
+        seq.reverse();
+    }
+    seq
+}
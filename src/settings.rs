@@ -1,5 +1,168 @@
+use auth::AdminAccount;
+use diagnostics::Thresholds;
+use sampling::{Counterbalance, BlockKey};
+use study::{StudyConfig, Mode, Question, Demographic, SurfaceFilter, Sampling};
+
 pub const DATADIRS: &[&str] = &["/mnt/usbstick/proton_data", "/mnt/vertical/proton_data"];
+
+/// Accounts allowed to open the management and analysis pages
+///
+/// Generate an entry with `cargo run -- hash-password <name>` (the password is read from stdin).
+pub const ADMINS: &[AdminAccount] = &[];
+
+/// Registry of participant IDs, display names and statuses (shared by all studies)
+pub const PARTICIPANTS: &str = "participants.json";
+
+/// Serve a mock crowdsourcing platform at /mock_platform for testing entry links and completion codes
+/// (debug builds only, so it can't end up in a deployment by accident)
+pub const MOCK_PLATFORM: bool = cfg!(debug_assertions);
+
+// output files (one of each per study)
 pub const RATINGS: &str = "ratings.csv";
 pub const REPORTS: &str = "reports.csv";
+pub const CHECKS: &str = "checks.csv";
+pub const ORDERS: &str = "orders.csv";
+pub const TRIALS: &str = "trials.csv";
+pub const EXCLUSIONS: &str = "exclusions.csv";
+pub const PAIRS: &str = "pairs.csv";
+pub const RANKINGS: &str = "rankings.csv";
+pub const LABELS: &str = "labels.csv";
+pub const PRACTICE: &str = "practice.csv";
+pub const ONBOARDING: &str = "onboarding.csv";
+pub const COMPLETIONS: &str = "completions.csv";
+/// Raters left out of every export of the study (saved from the diagnostics page, or edited by hand)
+pub const RATER_EXCLUSIONS: &str = "rater_exclusions.csv";
+
+/// Version of the consent form (participants who agreed to an older version are asked again)
+pub const CONSENT_VERSION: &str = "2017-07-1";
+
+/// Number of random candidate pairs considered when scheduling a pairwise trial
+pub const PAIR_CANDIDATES: usize = 50;
+
+/// Surfaces per page of the admin surface list (unless the query asks for another page size)
+pub const LIST_PAGE_SIZE: usize = 100;
+
+/// Largest page size the admin surface list will serve
+pub const LIST_MAX_PAGE_SIZE: usize = 1000;
+
+/// Number of resamples for bootstrap confidence intervals in the reliability analysis
+pub const BOOTSTRAP_SAMPLES: usize = 1000;
+
+/// Coverage of bootstrap confidence intervals
+pub const CONFIDENCE: f64 = 0.95;
+
+/// Limits of the rater quality diagnostics (raters beyond them end up on the suggested exclusion list)
+pub const DIAGNOSTICS: Thresholds = Thresholds {
+    min_trials: 10,
+    min_variance: 0.25,
+    max_identical: 0.5,
+    min_latency: 2.0,
+    min_consensus: 0.2,
+    min_gold: 0.5,
+};
+
+/// Users who fail more attention checks than this are excluded from exports
+pub const MAX_CHECK_FAILURES: u32 = 2;
+
+/// Questions about the four surface properties rated by the experimenter
+pub const PROPERTIES: &[Question] = &[
+    Question {
+        short: "warm",
+        prompt: "What temperature would you feel when touching this surface?",
+        low: "ice cold beer bottle",
+        high: "hot sand at the beach",
+        more: "warmer",
+    },
+    Question {
+        short: "hard",
+        prompt: "How soft or hard is this surface?",
+        low: "pillow",
+        high: "rock",
+        more: "harder",
+    },
+    Question {
+        short: "rough",
+        prompt: "How smooth or rough is this surface?",
+        low: "glass",
+        high: "sandpaper",
+        more: "rougher",
+    },
+    Question {
+        short: "sticky",
+        prompt: "How slippery or sticky is this surface? This is NOT the same as roughness, nor is it sticky as in glue. \
+                 This question refers to how much a finger would get stuck while rubbing due to friction with the surface.",
+        low: "silk",
+        high: "rubber",
+        more: "stickier",
+    },
+];
 
+/// Demographics questionnaire answered before the first trial
+pub const DEMOGRAPHICS: &[Demographic] = &[
+    Demographic {
+        short: "age",
+        prompt: "What is your age?",
+        options: &["18-24", "25-34", "35-44", "45-54", "55-64", "65 or older", "Prefer not to say"],
+    },
+    Demographic {
+        short: "handedness",
+        prompt: "Which is your dominant hand?",
+        options: &["Right", "Left", "Both", "Prefer not to say"],
+    },
+    Demographic {
+        short: "expertise",
+        prompt: "Do you have a background working with materials?",
+        options: &["None", "Hobby (e.g. crafts, woodworking)", "Professional (e.g. materials science, manufacturing, design)", "Prefer not to say"],
+    },
+];
 
+/// Studies hosted by this server (the first one is served at the old top-level URLs)
+///
+/// Gold surfaces are given with the range of ratings considered correct, e.g.
+///
+/// ```ignore
+/// Gold {
+///     date: Datestamp(20170602), flow: FlowType::StickCam, num: 1,
+///     ranges: &[("hard", 4, 5), ("rough", 1, 2)]
+/// }
+/// ```
+///
+/// and between-subject conditions as e.g.
+///
+/// ```ignore
+/// conditions: &[
+///     Condition { name: "image", template: None, flags: &[] },
+///     Condition { name: "experimenter", template: None, flags: &["experimenter"] },
+///     Condition { name: "zoom", template: None, flags: &["zoom"] },
+/// ]
+/// ```
+///
+/// For recruiting online, give participants an entry link like
+/// `/login?uri=/study/main/random&worker=<worker ID>` and set e.g.
+///
+/// ```ignore
+/// crowdsourcing: Some(Crowdsourcing { quota: 100, return_url: "/mock_platform/complete?worker={worker}&code={code}" }),
+/// ```
+pub const STUDIES: &[StudyConfig] = &[
+    StudyConfig {
+        id: "main",
+        title: "Surface material human ratings",
+        dir: ".",
+        mode: Mode::Likert,
+        questionnaire: PROPERTIES,
+        conditions: &[],
+        crowdsourcing: None,
+        practice: &[],
+        filter: SurfaceFilter { flows: &[], dates: None, episodes: &[] },
+        sampling: Sampling {
+            seed: 20170717,
+            counterbalance: Counterbalance::Blocks { by: BlockKey::FlowType, latin_square: true },
+            check_rate: 0.1,
+            gold_rate: 0.5,
+            gold: &[],
+            retire_after_reports: Some(5),
+            retire_report_fraction: Some((0.5, 4)),
+            adaptive: None,
+        },
+    },
+];
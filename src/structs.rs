@@ -1,3 +1,4 @@
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::io::BufReader;
 use std::fmt;
@@ -8,18 +9,27 @@ use std::str::FromStr;
 use std::sync::Mutex;
 
 use rocket;
-use rocket::http::RawStr;
+use rocket::http::{RawStr, Status};
 use rocket::request::{Request, FromRequest, FromParam, FromForm, FromFormValue, FormItems, Outcome};
 use rocket::outcome::IntoOutcome;
+use rocket::State;
 use flow::{Flow, FlowCmd};
 
 use errors::*;
+use participants::Participants;
+use sampling::Order;
+use settings;
+use study::Sampling;
 
-/// User ID (stored in a cookie and used to index into active users table)
+/// Logged-in participant (the ID is stored in a private cookie and indexes into the active users tables)
 #[derive(Serialize, Clone, Default, PartialEq, Eq, Hash)]
 pub struct User {
-    /// Name chosen by user at login screen
-    pub name: String
+    /// Server-generated participant ID (used in all output files)
+    pub id: String,
+    /// Display name chosen by user at login screen
+    pub name: String,
+    /// Worker ID on the crowdsourcing platform the user came from
+    pub worker: Option<String>
 }
 
 /// Server-side information about a user
@@ -27,16 +37,87 @@ pub struct User {
 pub struct UserInfo {
     /// Surfaces already rated or reported by this user
     pub seen: Vec<(Datestamp, FlowType, u32)>,
+    /// Trial order assigned to this user
+    pub order: Option<Order>,
+    /// Between-subject condition assigned to this user (index into the study's conditions)
+    pub condition: Option<usize>,
+    /// Pairs of surfaces (indices into the study's surface list, smaller first) already compared by this user
+    pub compared: HashSet<(usize, usize)>,
+    /// Pair of surfaces (smaller index first) of the comparison trial currently being shown
+    pub pair: Option<(usize, usize)>,
+    /// Number of ranking trials completed by this user
+    pub rankings: usize,
+    /// Surfaces (indices into the study's surface list) and question of the ranking trial currently being shown
+    pub ranking: Option<(Vec<usize>, &'static str)>,
+    /// Number of practice trials completed by this user
+    pub practiced: usize,
+    /// Version of the consent form agreed to, and when (seconds since the Unix epoch)
+    pub consent: Option<(String, u64)>,
+    /// Answers to the demographics questionnaire
+    pub demographics: HashMap<String, String>,
+    /// Whether the user has read the instructions page
+    pub instructed: bool,
+    /// Whether the user has been given a completion code
+    pub completed: bool,
+    /// Whether an admin excluded or banned this user
+    pub flagged: bool,
+    /// Whether this user is on an exclusion list of raters flagged by the diagnostics
+    pub listed: bool,
+    /// Attention check attached to the trial currently being shown
+    pub check: Option<Check>,
+    /// Number of attention checks passed
+    pub checks_passed: u32,
+    /// Number of attention checks failed
+    pub checks_failed: u32,
     /// Flash message for rating form
     pub rate_error: bool, // TODO use FlashMessage
     /// Flash message for report form
     pub report_error: bool // TODO use FlashMessage
 }
 
-/// Managed state type for active users table
-pub type ActiveUsers = Mutex<HashMap<User, UserInfo>>;
+impl UserInfo {
+    /// Number of trials (of any kind) completed in the main phase
+    pub fn trials(&self) -> usize {
+        self.seen.len() + self.compared.len() + self.rankings
+    }
+
+    /// Whether this user was excluded by an admin or has failed too many attention checks to be
+    /// included in exports
+    pub fn excluded(&self) -> bool {
+        self.flagged || self.listed || self.checks_failed > settings::MAX_CHECK_FAILURES
+    }
+}
+
+/// Managed state type for active users table (indexed by participant ID)
+pub type ActiveUsers = Mutex<HashMap<String, UserInfo>>;
 /// Managed state type for tracking reported bad surfaces
-pub type Reports = Mutex<HashSet<(Datestamp, FlowType, u32)>>;
+pub type Reports = Mutex<HashMap<(Datestamp, FlowType, u32), ReportTally>>;
+
+/// Report counts and exclusion status of one surface
+#[derive(Default)]
+pub struct ReportTally {
+    /// Number of times the surface was reported
+    pub reports: u32,
+    /// Number of times the surface was rated or reported
+    pub views: u32,
+    /// Admin decision overriding the automatic exclusion rule
+    pub exclude: Option<bool>
+}
+
+impl ReportTally {
+    /// Whether the automatic rule would retire this surface
+    pub fn auto_excluded(&self, sampling: &Sampling) -> bool {
+        sampling.retire_after_reports.map_or(false, |k| self.reports >= k)
+            || sampling.retire_report_fraction.map_or(false, |(frac, min_views)| {
+                   self.views >= min_views && self.reports as f64 >= frac * self.views as f64
+               })
+    }
+
+    /// Whether this surface should be skipped in sampling
+    pub fn excluded(&self, sampling: &Sampling) -> bool {
+        self.exclude.unwrap_or_else(|| self.auto_excluded(sampling))
+    }
+}
 
 /// 1-5 rating of a surface property
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
@@ -66,7 +147,7 @@ with_user! {
 
 with_user! {
     /// Surface info for passing to a template
-    #[derive(Serialize, Deserialize)]
+    #[derive(Clone, Serialize, Deserialize)]
     pub struct SurfaceData/SurfaceDataWithUser<String> {
         /// Episode date (e.g. $DATADIR/$date/$flow/$num)
         pub date: Datestamp,
@@ -81,6 +162,309 @@ with_user! {
     }
 }
 
+/// Gold-standard surface with known rating ranges
+pub struct Gold {
+    /// Episode date
+    pub date: Datestamp,
+    /// Episode flow type
+    pub flow: FlowType,
+    /// Episode number
+    pub num: u32,
+    /// Inclusive range of acceptable answers for each rating
+    pub ranges: &'static [(&'static str, u8, u8)]
+}
+
+/// Attention check mixed into the trial stream
+#[derive(Clone, Serialize)]
+#[serde(tag="kind", rename_all="lowercase")]
+pub enum Check {
+    /// The surface is a gold surface and the ratings must fall within its ranges
+    Gold { date: Datestamp, flow: FlowType, num: u32 },
+    /// The rater is told to select a particular answer for an extra question
+    Instructed { date: Datestamp, flow: FlowType, num: u32, answer: Likert }
+}
+
+/// Row of the attention check results file
+#[derive(Serialize, Deserialize)]
+pub struct CheckRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Date")]
+    pub date: Datestamp,
+    #[serde(rename="Flow type")]
+    pub flow: FlowType,
+    #[serde(rename="Number")]
+    pub num: u32,
+    #[serde(rename="Kind")]
+    pub kind: String,
+    #[serde(rename="Passed")]
+    pub passed: bool
+}
+
+/// Row of the trial log (only the columns needed for timing)
+#[derive(Deserialize)]
+pub struct TrialRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Date")]
+    pub date: Datestamp,
+    #[serde(rename="Flow type")]
+    pub flow: FlowType,
+    #[serde(rename="Number")]
+    pub num: u32,
+    /// "rating", "gold", "report", "label" etc.
+    #[serde(rename="Response")]
+    pub response: String,
+    #[serde(rename="Time")]
+    pub time: u64
+}
+
+/// Row of the onboarding log (consent, one row per demographics answer, and instructions)
+#[derive(Serialize, Deserialize)]
+pub struct OnboardingRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Step")]
+    pub step: String,
+    #[serde(rename="Answer")]
+    pub answer: String,
+    #[serde(rename="Time")]
+    pub time: u64
+}
+
+/// Answers to an onboarding form (field name to value)
+pub struct Answers(pub HashMap<String, String>);
+
+/// Row of the completion codes file
+#[derive(Serialize, Deserialize)]
+pub struct CompletionRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Worker")]
+    pub worker: String,
+    #[serde(rename="Code")]
+    pub code: String,
+    #[serde(rename="Time")]
+    pub time: u64
+}
+
+/// Submission to the mock crowdsourcing platform
+#[derive(FromForm)]
+pub struct Submission {
+    /// Worker ID
+    pub worker: String,
+    /// Completion code
+    pub code: String
+}
+
+/// Row of the trial order assignments file
+#[derive(Serialize, Deserialize)]
+pub struct OrderRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Seed")]
+    pub seed: u64,
+    #[serde(rename="Assignment")]
+    pub assignment: usize,
+    #[serde(rename="Condition", default)]
+    pub condition: Option<String>
+}
+
+/// Row of the exclusion overrides file
+#[derive(Serialize, Deserialize)]
+pub struct ExclusionRecord {
+    #[serde(rename="Date")]
+    pub date: Datestamp,
+    #[serde(rename="Flow type")]
+    pub flow: FlowType,
+    #[serde(rename="Number")]
+    pub num: u32,
+    /// "exclude", "include" or "auto"
+    #[serde(rename="Override")]
+    pub action: String
+}
+
+/// Inputs from the exclusion override form
+#[derive(FromForm)]
+pub struct Exclusion {
+    /// Episode date
+    pub date: Datestamp,
+    /// Episode flow type
+    pub flow: FlowType,
+    /// Episode number
+    pub num: u32,
+    /// "exclude", "include" or "auto"
+    pub action: String
+}
+
+impl Exclusion {
+    /// Override value corresponding to the chosen action
+    pub fn exclude(action: &str) -> Result<Option<bool>> {
+        match action {
+            "exclude" => Ok(Some(true)),
+            "include" => Ok(Some(false)),
+            "auto" => Ok(None),
+            _ => Err(ErrorKind::BadParam("invalid exclusion override").into())
+        }
+    }
+}
+
+/// One of the two surfaces in a pairwise comparison
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B
+}
+
+/// Pairwise comparison submitted from the form
+pub struct Comparison {
+    /// Surface shown on the left
+    pub a: (Datestamp, FlowType, u32),
+    /// Surface shown on the right
+    pub b: (Datestamp, FlowType, u32),
+    /// Chosen surface for each question
+    pub choices: HashMap<String, Side>
+}
+
+/// Row of the pairwise comparisons file (one per question)
+#[derive(Serialize, Deserialize)]
+pub struct PairRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Date A")]
+    pub a_date: Datestamp,
+    #[serde(rename="Flow type A")]
+    pub a_flow: FlowType,
+    #[serde(rename="Number A")]
+    pub a_num: u32,
+    #[serde(rename="Date B")]
+    pub b_date: Datestamp,
+    #[serde(rename="Flow type B")]
+    pub b_flow: FlowType,
+    #[serde(rename="Number B")]
+    pub b_num: u32,
+    #[serde(rename="Question")]
+    pub question: String,
+    /// "A" or "B"
+    #[serde(rename="Winner")]
+    pub winner: String
+}
+
+impl PairRecord {
+    /// The (winner, loser) surfaces of this comparison
+    pub fn outcome(&self) -> ((Datestamp, FlowType, u32), (Datestamp, FlowType, u32)) {
+        let a = (self.a_date, self.a_flow, self.a_num);
+        let b = (self.b_date, self.b_flow, self.b_num);
+        if self.winner == "A" { (a, b) } else { (b, a) }
+    }
+}
+
+/// Inputs from the ranking form
+#[derive(FromForm)]
+pub struct RankingForm {
+    /// Short name of the question the surfaces were ranked by
+    pub question: String,
+    /// Surfaces in displayed order, as "date/flow/num" separated by semicolons
+    pub surfaces: String,
+    /// Displayed positions in rank order, separated by commas (filled in by the page script)
+    pub order: String
+}
+
+impl RankingForm {
+    /// Parse the displayed surfaces (each may appear only once)
+    pub fn surfaces(&self) -> Result<Vec<(Datestamp, FlowType, u32)>> {
+        let surfaces = self.surfaces.split(';')
+                                    .map(|key| {
+                                        let parts = key.split('/').collect::<Vec<_>>();
+                                        if parts.len() != 3 {
+                                            return Err(ErrorKind::BadParam("invalid surface").into());
+                                        }
+                                        Ok((Datestamp(parts[0].parse::<u32>().chain_err(|| ErrorKind::BadParam("invalid date"))?),
+                                            parts[1].parse::<FlowType>().map_err(ErrorKind::BadParam)?,
+                                            parts[2].parse::<u32>().chain_err(|| ErrorKind::BadParam("invalid episode number"))?))
+                                    })
+                                    .collect::<Result<Vec<_>>>()?;
+        if surfaces.iter().collect::<HashSet<_>>().len() != surfaces.len() {
+            Err(ErrorKind::BadParam("surface ranked more than once"))?;
+        }
+        Ok(surfaces)
+    }
+
+    /// Parse the ranking, if it is a complete permutation of the displayed positions
+    pub fn order(&self, n: usize) -> Option<Vec<usize>> {
+        let order = self.order.split(',')
+                              .map(|s| s.parse().ok())
+                              .collect::<Option<Vec<usize>>>();
+        match order {
+            Some(order) => {
+                let mut sorted = order.clone();
+                sorted.sort();
+                if sorted == (0..n).collect::<Vec<_>>() { Some(order) } else { None }
+            }
+            None => None
+        }
+    }
+}
+
+/// Row of the rankings file (one per ranked surface)
+#[derive(Serialize, Deserialize)]
+pub struct RankingRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    /// Sequence number of the ranking trial for this user
+    #[serde(rename="Trial")]
+    pub trial: usize,
+    #[serde(rename="Question")]
+    pub question: String,
+    #[serde(rename="Date")]
+    pub date: Datestamp,
+    #[serde(rename="Flow type")]
+    pub flow: FlowType,
+    #[serde(rename="Number")]
+    pub num: u32,
+    /// Position at which the surface was displayed (0-based)
+    #[serde(rename="Position")]
+    pub position: usize,
+    /// Rank given by the user (1 = most)
+    #[serde(rename="Rank")]
+    pub rank: usize
+}
+
+/// Category recorded when the rater chooses "don't know"
+pub const DONT_KNOW: &str = "unknown";
+
+/// Inputs from the labeling form
+#[derive(FromForm)]
+pub struct Label {
+    /// Episode date
+    pub date: Datestamp,
+    /// Episode flow type
+    pub flow: FlowType,
+    /// Episode number
+    pub num: u32,
+    /// Chosen category (or "unknown")
+    pub category: Option<String>,
+    /// Free-text description
+    pub description: Option<String>
+}
+
+/// Row of the labels file
+#[derive(Serialize, Deserialize)]
+pub struct LabelRecord {
+    #[serde(rename="User")]
+    pub user: String,
+    #[serde(rename="Date")]
+    pub date: Datestamp,
+    #[serde(rename="Flow type")]
+    pub flow: FlowType,
+    #[serde(rename="Number")]
+    pub num: u32,
+    #[serde(rename="Category")]
+    pub category: String,
+    #[serde(rename="Description")]
+    pub description: String
+}
+
 /// Rating loaded from flow file
 #[derive(Default, Serialize, Deserialize)]
 pub struct Rating {
@@ -91,11 +475,11 @@ pub struct Rating {
 }
 
 /// YYYYMMDD date
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Datestamp(pub u32);
 
 /// Episode type (end-effector type)
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(rename_all="lowercase")]
 pub enum FlowType {
     /// Rigid stick
@@ -112,14 +496,225 @@ pub struct Login {
     /// URL to redirect back to after logging in
     pub redir: String,
     /// Chosen username
-    pub user_name: String
+    pub user_name: String,
+    /// Worker ID passed in by a crowdsourcing platform (replaces the username)
+    pub worker: Option<String>
+}
+
+/// Inputs to the API login (the login form without the redirect)
+#[derive(FromForm)]
+pub struct ApiLogin {
+    /// Chosen username
+    pub user_name: String,
+    /// Worker ID passed in by a crowdsourcing platform (replaces the username)
+    pub worker: Option<String>
+}
+
+/// Inputs from admin login form
+#[derive(FromForm)]
+pub struct AdminLogin {
+    /// URL to redirect back to after logging in
+    pub redir: String,
+    /// Admin account name
+    pub name: String,
+    /// Admin password
+    pub password: String
+}
+
+/// Inputs from the participant status form
+#[derive(FromForm)]
+pub struct StatusChange {
+    /// Participant ID
+    pub id: String,
+    /// "active", "excluded" or "banned"
+    pub status: String
+}
+
+/// Inputs from the participant merge form
+#[derive(FromForm)]
+pub struct Merge {
+    /// ID of the duplicate participant
+    pub from: String,
+    /// ID of the participant to keep
+    pub into: String
+}
+
+/// Inputs from the participant withdrawal form
+#[derive(FromForm)]
+pub struct Withdrawal {
+    /// Participant ID
+    pub id: String,
+    /// Must repeat the participant ID, as a safeguard
+    pub confirm: String
+}
+
+/// Passing the referer to the admin login page
+#[derive(FromForm)]
+pub struct AdminReferer {
+    /// Referring URL
+    pub uri: String,
+    /// Set if the previous login attempt failed
+    pub failed: Option<String>
+}
+
+/// Options for the per-surface dataset export (query string of the export link, or CLI flags)
+#[derive(FromForm, Default)]
+pub struct DatasetOptions {
+    /// "csv" or "jsonl"
+    pub format: String,
+    /// Keep raters who are excluded from the other exports
+    pub include_excluded: bool,
+    /// Also leave out raters flagged by the quality diagnostics
+    pub exclude_flagged: bool,
+    /// Leave out surfaces rated fewer times than this
+    pub min_count: Option<u32>,
+    /// Replace each rating by its z-score among the same rater's ratings on that dimension
+    pub normalize: bool
+}
+
+/// Filters, sorting and page of the admin surface list (query string of /study/<id>/list)
+///
+/// Experimenter rating ranges are given as `min_<question>` and `max_<question>`. Empty values are
+/// ignored, so the filter form can be submitted as is.
+#[derive(Serialize, Clone)]
+pub struct ListQuery {
+    /// First episode date
+    pub from: Option<Datestamp>,
+    /// Last episode date
+    pub to: Option<Datestamp>,
+    /// End-effector type
+    pub flow: Option<FlowType>,
+    /// Only surfaces that were (`Some(true)`) or weren't (`Some(false)`) reported
+    pub reported: Option<bool>,
+    /// Minimum number of ratings
+    pub min_ratings: Option<u32>,
+    /// Maximum number of ratings
+    pub max_ratings: Option<u32>,
+    /// Inclusive range of the experimenter's rating for some questions
+    pub experimenter: HashMap<String, (Option<u8>, Option<u8>)>,
+    /// "episode", "reports", "views", "ratings", "ci" or a question (experimenter rating)
+    pub sort: String,
+    /// Sort in descending order
+    pub desc: bool,
+    /// Page number (starting at 1)
+    pub page: usize,
+    /// Surfaces per page
+    pub per_page: usize,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        ListQuery {
+            from: None,
+            to: None,
+            flow: None,
+            reported: None,
+            min_ratings: None,
+            max_ratings: None,
+            experimenter: HashMap::new(),
+            sort: "episode".into(),
+            desc: false,
+            page: 1,
+            per_page: settings::LIST_PAGE_SIZE,
+        }
+    }
+}
+
+impl ListQuery {
+    /// Whether a surface passes the filters, given its number of reports and ratings
+    pub fn matches(&self, surf: &SurfaceData, reports: u32, ratings: u32) -> bool {
+        self.from.map_or(true, |d| surf.date >= d)
+            && self.to.map_or(true, |d| surf.date <= d)
+            && self.flow.map_or(true, |f| surf.flow == f)
+            && self.reported.map_or(true, |r| r == (reports > 0))
+            && self.min_ratings.map_or(true, |n| ratings >= n)
+            && self.max_ratings.map_or(true, |n| ratings <= n)
+            && self.experimenter.iter().all(|(q, &(min, max))| {
+                   match surf.ratings.get(q) {
+                       Some(&Likert(r)) => min.map_or(true, |m| r >= m) && max.map_or(true, |m| r <= m),
+                       None => false
+                   }
+               })
+    }
+
+    /// Query string selecting another page with the same filters and sorting
+    pub fn to_query(&self, page: usize) -> String {
+        let mut params = vec![];
+        if let Some(d) = self.from { params.push(format!("from={}", d)); }
+        if let Some(d) = self.to { params.push(format!("to={}", d)); }
+        if let Some(f) = self.flow { params.push(format!("flow={}", f)); }
+        if let Some(r) = self.reported { params.push(format!("reported={}", r)); }
+        if let Some(n) = self.min_ratings { params.push(format!("min_ratings={}", n)); }
+        if let Some(n) = self.max_ratings { params.push(format!("max_ratings={}", n)); }
+        for (q, &(min, max)) in &self.experimenter {
+            if let Some(m) = min { params.push(format!("min_{}={}", q, m)); }
+            if let Some(m) = max { params.push(format!("max_{}={}", q, m)); }
+        }
+        params.push(format!("sort={}", self.sort));
+        if self.desc { params.push("desc=true".into()); }
+        params.push(format!("page={}", page));
+        params.push(format!("per_page={}", self.per_page));
+        params.join("&")
+    }
+}
+
+impl<'f> FromForm<'f> for ListQuery {
+    type Error = rocket::Error;
+
+    fn from_form(items: &mut FormItems<'f>, _strict: bool) -> StdResult<Self, Self::Error> {
+        fn parse<'v, T: FromFormValue<'v>>(value: &'v RawStr) -> StdResult<T, rocket::Error> {
+            T::from_form_value(value).map_err(|_| rocket::Error::BadParse)
+        }
+
+        let mut query = ListQuery::default();
+        for (key, value) in items {
+            if value.as_str().is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                "from" => query.from = Some(parse(value)?),
+                "to" => query.to = Some(parse(value)?),
+                "flow" => query.flow = Some(parse(value)?),
+                "reported" => query.reported = Some(parse(value)?),
+                "min_ratings" => query.min_ratings = Some(parse(value)?),
+                "max_ratings" => query.max_ratings = Some(parse(value)?),
+                "sort" => query.sort = value.url_decode().map_err(|_| rocket::Error::BadParse)?,
+                "desc" => query.desc = parse(value)?,
+                "page" => query.page = cmp::max(parse(value)?, 1),
+                "per_page" => query.per_page = cmp::min(cmp::max(parse(value)?, 1), settings::LIST_MAX_PAGE_SIZE),
+                key if key.starts_with("min_") => {
+                    query.experimenter.entry(key[4..].to_owned()).or_insert((None, None)).0 = Some(parse(value)?);
+                }
+                key if key.starts_with("max_") => {
+                    query.experimenter.entry(key[4..].to_owned()).or_insert((None, None)).1 = Some(parse(value)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(query)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ListQuery {
+    type Error = ();
+
+    fn from_request(req: &'a Request<'r>) -> Outcome<Self, ()> {
+        match req.uri().query() {
+            Some(query) => ListQuery::from_form(&mut FormItems::from(query), false).map_err(|_| ()).into_outcome(Status::BadRequest),
+            None => Outcome::Success(ListQuery::default())
+        }
+    }
 }
 
 /// Passing the referer as a query param
 #[derive(FromForm)]
 pub struct Referer {
     /// Referring URL
-    pub uri: String
+    pub uri: String,
+    /// Name rejected at the previous login attempt because another participant has it
+    pub taken: Option<String>,
+    /// Worker ID passed in by a crowdsourcing platform's entry link
+    pub worker: Option<String>
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for Referer {
@@ -128,7 +723,7 @@ impl<'a, 'r> FromRequest<'a, 'r> for Referer {
     fn from_request(req: &'a Request<'r>) -> Outcome<Self, ()> {
         req.headers()
            .get_one("Referer")
-           .map(|s| Referer { uri: s.into() })
+           .map(|s| Referer { uri: s.into(), taken: None, worker: None })
            .or_forward(())
     }
 }
@@ -139,10 +734,15 @@ impl<'a, 'r> FromRequest<'a, 'r> for User {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<User, ()> {
-        request.cookies()
-               .get("user")
-               .map(|c| User { name: c.value().to_owned() })
-               .or_forward(())
+        let id = match request.cookies().get_private("user") {
+            Some(cookie) => cookie.value().to_owned(),
+            None => return Outcome::Forward(())
+        };
+        let participants = match request.guard::<State<Participants>>() {
+            Outcome::Success(participants) => participants,
+            _ => return Outcome::Forward(())
+        };
+        participants.user(&id).or_forward(())
     }
 }
 
@@ -193,6 +793,83 @@ impl<'f> FromForm<'f> for SurfaceData {
     }
 }
 
+impl<'f> FromForm<'f> for Answers {
+    type Error = rocket::Error;
+
+    fn from_form(items: &mut FormItems<'f>, _strict: bool) -> StdResult<Self, Self::Error> {
+        let mut answers = HashMap::new();
+        for (key, value) in items {
+            let value = value.url_decode().map_err(|_| rocket::Error::BadParse)?;
+            answers.insert(key.as_str().to_owned(), value);
+        }
+        Ok(Answers(answers))
+    }
+}
+
+impl<'f> FromForm<'f> for Comparison {
+    type Error = rocket::Error;
+
+    fn from_form(items: &mut FormItems<'f>, strict: bool) -> StdResult<Self, Self::Error> {
+        let (mut a_date, mut a_flow, mut a_num) = (None, None, None);
+        let (mut b_date, mut b_flow, mut b_num) = (None, None, None);
+        let mut choices = HashMap::new();
+
+        macro_rules! arm {
+            ($val:expr, $fld:ident) => {
+                $fld = Some(FromFormValue::from_form_value($val).map_err(|_| rocket::Error::BadParse)?)
+            }
+        }
+        for (key, value) in items {
+            match key.as_str() {
+                "a_date" => arm!(value, a_date),
+                "a_flow" => arm!(value, a_flow),
+                "a_num" => arm!(value, a_num),
+                "b_date" => arm!(value, b_date),
+                "b_flow" => arm!(value, b_flow),
+                "b_num" => arm!(value, b_num),
+                s => {
+                    if let Ok(side) = Side::from_form_value(value) {
+                        choices.insert(s.to_string(), side);
+                    } else if strict {
+                        return Err(rocket::Error::BadParse);
+                    }
+                }
+            }
+        }
+
+        if let (Some(a_date), Some(a_flow), Some(a_num), Some(b_date), Some(b_flow), Some(b_num)) = (a_date, a_flow, a_num, b_date, b_flow, b_num) {
+            Ok(Comparison {
+                a: (a_date, a_flow, a_num),
+                b: (b_date, b_flow, b_num),
+                choices
+            })
+        } else {
+            Err(rocket::Error::BadParse)
+        }
+    }
+}
+
+impl<'v> FromFormValue<'v> for Side {
+    type Error = &'static str;
+
+    fn from_form_value(v: &'v RawStr) -> StdResult<Self, Self::Error> {
+        match v.as_str() {
+            "a" => Ok(Side::A),
+            "b" => Ok(Side::B),
+            _ => Err("invalid side")
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Side::A => write!(f, "A"),
+            Side::B => write!(f, "B"),
+        }
+    }
+}
+
 impl FromStr for Likert {
     type Err = &'static str;
 
@@ -220,11 +897,16 @@ impl<'v> FromFormValue<'v> for Datestamp {
     }
 }
 
+/// Parse an episode's flow file
+pub fn parse_flow(flowname: FlowType, path: &Path) -> Result<Flow> {
+    Ok(Flow::parse(format!("{}", flowname),
+                   BufReader::new(File::open(path)?))
+           .chain_err(|| ErrorKind::Parse(path.to_owned()))?)
+}
+
 impl SurfaceData {
     pub fn from_flow_file(date: Datestamp, flowname: FlowType, num: u32, path: &Path) -> Result<Self> {
-        let flow = Flow::parse(format!("{}", flowname),
-                               BufReader::new(File::open(path)?))
-                       .chain_err(|| ErrorKind::Parse(path.to_owned()))?;
+        let flow = parse_flow(flowname, path)?;
         Ok(Self {
             flow: flowname,
             date, num,
@@ -240,6 +922,47 @@ impl SurfaceData {
     }
 }
 
+impl Check {
+    /// Does this check belong to the given surface?
+    pub fn is_for(&self, d: Datestamp, f: FlowType, n: u32) -> bool {
+        match *self {
+            Check::Gold { date, flow, num } | Check::Instructed { date, flow, num, .. } => (date, flow, num) == (d, f, n)
+        }
+    }
+
+    /// Short name written to the results file
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Check::Gold { .. } => "gold",
+            Check::Instructed { .. } => "instructed"
+        }
+    }
+
+    /// Answer expected for the instructed-response item, if there is one
+    pub fn attention(&self) -> Option<Likert> {
+        match *self {
+            Check::Instructed { answer, .. } => Some(answer),
+            Check::Gold { .. } => None
+        }
+    }
+
+    /// Grade a set of answers against this check
+    ///
+    /// Returns `None` if the check can't be graded (a gold check whose surface is no longer in the gold list).
+    pub fn passed(&self, gold: &[Gold], ratings: &HashMap<String, Likert>, attention: Option<Likert>) -> Option<bool> {
+        match *self {
+            Check::Gold { date, flow, num } => {
+                gold.iter()
+                    .find(|g| (g.date, g.flow, g.num) == (date, flow, num))
+                    .map(|g| g.ranges.iter().all(|&(short, lo, hi)| {
+                        ratings.get(short).map(|r| lo <= r.0 && r.0 <= hi).unwrap_or(false)
+                    }))
+            }
+            Check::Instructed { answer, .. } => Some(attention == Some(answer))
+        }
+    }
+}
+
 impl<'a> FromParam<'a> for Datestamp {
     type Error = String;
     fn from_param(param: &'a RawStr) -> StdResult<Self, Self::Error> {
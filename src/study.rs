@@ -0,0 +1,624 @@
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use csv;
+
+use analysis::Moments;
+use sampling::{Adaptive, Counterbalance, Order, user_seed};
+use settings;
+use errors::*;
+use structs::*;
+use utils::*;
+
+/// Static configuration of one study
+pub struct StudyConfig {
+    /// URL component (e.g. /study/$id/random)
+    pub id: &'static str,
+    /// Human-readable name
+    pub title: &'static str,
+    /// Directory holding this study's output files
+    pub dir: &'static str,
+    /// Kind of trial presented to participants
+    pub mode: Mode,
+    /// Rating questions asked about each surface
+    pub questionnaire: &'static [Question],
+    /// Between-subject conditions, one of which is assigned to each participant (may be empty)
+    pub conditions: &'static [Condition],
+    /// Recruitment through a crowdsourcing platform (participants get a completion code after a quota of trials)
+    pub crowdsourcing: Option<Crowdsourcing>,
+    /// Training surfaces rated before the main phase, with the experimenter's ratings shown as feedback
+    pub practice: &'static [(Datestamp, FlowType, u32)],
+    /// Which scanned surfaces belong to this study
+    pub filter: SurfaceFilter,
+    /// How surfaces are chosen for each participant
+    pub sampling: Sampling,
+}
+
+/// Kind of trial presented to participants
+#[allow(dead_code)]
+pub enum Mode {
+    /// Rate one surface at a time on the questionnaire's Likert scales
+    Likert,
+    /// Choose which of two surfaces has more of each questionnaire property
+    Pairwise,
+    /// Order a number of surfaces by one questionnaire property
+    Ranking { size: usize },
+    /// Put each surface in a material category and optionally describe it
+    Labeling {
+        /// Category vocabulary (a "don't know" answer is always offered too)
+        categories: &'static [&'static str],
+        /// Show a free-text description box
+        describe: bool
+    },
+}
+
+/// Settings for recruiting participants through a crowdsourcing platform
+pub struct Crowdsourcing {
+    /// Number of main-phase trials after which a participant is done
+    pub quota: usize,
+    /// Where to send participants once they are done ("{code}" and "{worker}" are filled in)
+    pub return_url: &'static str,
+}
+
+/// Between-subject experimental condition
+pub struct Condition {
+    /// Name recorded in the output files
+    pub name: &'static str,
+    /// Template rendered instead of episode.html.tera for Likert trials
+    pub template: Option<&'static str>,
+    /// Flags passed to the templates (e.g. "zoom" or "experimenter")
+    pub flags: &'static [&'static str],
+}
+
+/// One Likert question in a questionnaire
+#[derive(Serialize)]
+pub struct Question {
+    /// Short name (used for radio button name and output column)
+    pub short: &'static str,
+    /// Full question shown to the participant
+    pub prompt: &'static str,
+    /// Anchor for a rating of 1
+    pub low: &'static str,
+    /// Anchor for a rating of 5
+    pub high: &'static str,
+    /// Comparative form of the property (e.g. "rougher") for pairwise trials
+    pub more: &'static str,
+}
+
+/// Multiple-choice question in the onboarding demographics questionnaire
+#[derive(Serialize)]
+pub struct Demographic {
+    /// Short name (used for form field and output)
+    pub short: &'static str,
+    /// Full question shown to the participant
+    pub prompt: &'static str,
+    /// Allowed answers
+    pub options: &'static [&'static str],
+}
+
+/// Selects the surfaces used in a study (empty lists match everything)
+pub struct SurfaceFilter {
+    /// Allowed end-effector types
+    pub flows: &'static [FlowType],
+    /// Inclusive range of episode dates
+    pub dates: Option<(Datestamp, Datestamp)>,
+    /// Explicit list of episodes
+    pub episodes: &'static [(Datestamp, FlowType, u32)],
+}
+
+/// Sampling policy for a study
+pub struct Sampling {
+    /// Seed from which every participant's trial order is derived
+    pub seed: u64,
+    /// How trial orders are counterbalanced across participants
+    pub counterbalance: Counterbalance,
+    /// Fraction of trials that carry an attention check
+    pub check_rate: f64,
+    /// Fraction of attention checks that are gold surfaces (the rest are instructed-response items)
+    pub gold_rate: f64,
+    /// Gold-standard surfaces with the range of ratings considered correct
+    pub gold: &'static [Gold],
+    /// Retire a surface once it has been reported this many times
+    pub retire_after_reports: Option<u32>,
+    /// Retire a surface once this fraction of its viewers reported it (after a minimum number of views)
+    pub retire_report_fraction: Option<(f64, u32)>,
+    /// Spend ratings on the most uncertain surfaces once every surface has basic coverage
+    pub adaptive: Option<Adaptive>,
+}
+
+/// Runtime state of one study
+pub struct Study {
+    /// Static configuration
+    pub config: &'static StudyConfig,
+    /// Surfaces passing the study's filter, sorted by episode
+    pub surfaces: Vec<SurfaceData>,
+    /// Practice surfaces, in the configured order
+    pub practice: Vec<SurfaceData>,
+    /// Participants of this study
+    pub users: ActiveUsers,
+    /// Reported surfaces in this study
+    pub reports: Reports,
+    /// Running mean and variance of each questionnaire dimension per surface
+    pub moments: Mutex<HashMap<(Datestamp, FlowType, u32), Vec<Moments>>>,
+    /// Number of users who compared each pair of surfaces (indices into `surfaces`, smaller first)
+    pub comparisons: Mutex<HashMap<(usize, usize), u32>>,
+}
+
+/// Managed state type for all studies
+pub type Studies = Vec<Study>;
+
+/// Output files with one participant's data per row (the participant ID is in the first column)
+const PARTICIPANT_FILES: &[&str] = &[settings::RATINGS, settings::PRACTICE, settings::REPORTS, settings::CHECKS,
+                                     settings::ORDERS, settings::PAIRS, settings::RANKINGS, settings::LABELS,
+                                     settings::ONBOARDING, settings::COMPLETIONS, settings::TRIALS,
+                                     settings::RATER_EXCLUSIONS];
+
+/// Output file columns, plus a condition column if the study has conditions
+pub fn with_condition(config: &StudyConfig, headers: &[&str]) -> Vec<String> {
+    let mut headers = headers.iter().map(|&h| h.to_owned()).collect::<Vec<_>>();
+    if !config.conditions.is_empty() {
+        headers.push("Condition".into());
+    }
+    headers
+}
+
+/// Add one set of answers (in questionnaire order) to a surface's running moments
+pub fn tally_ratings(moments: &mut HashMap<(Datestamp, FlowType, u32), Vec<Moments>>, key: (Datestamp, FlowType, u32), answers: &[Likert]) {
+    let entry = moments.entry(key).or_insert_with(|| vec![Default::default(); answers.len()]);
+    for (m, a) in entry.iter_mut().zip(answers) {
+        m.push(a.0 as f64);
+    }
+}
+
+impl SurfaceFilter {
+    pub fn matches(&self, surf: &SurfaceData) -> bool {
+        (self.flows.is_empty() || self.flows.contains(&surf.flow))
+            && self.dates.map_or(true, |(from, to)| from <= surf.date && surf.date <= to)
+            && (self.episodes.is_empty() || self.episodes.contains(&(surf.date, surf.flow, surf.num)))
+    }
+}
+
+impl Study {
+    /// Look up a study by its URL component
+    pub fn find<'a>(studies: &'a [Study], id: &str) -> Result<&'a Study> {
+        studies.iter()
+               .find(|s| s.config.id == id)
+               .ok_or_else(|| ErrorKind::UnknownStudy(id.to_owned()).into())
+    }
+
+    /// Condition assigned to a participant
+    pub fn condition(&self, info: &UserInfo) -> Option<&'static Condition> {
+        info.condition.map(|c| &self.config.conditions[c])
+    }
+
+    /// Trailing output column recording a participant's condition (empty if the study has no conditions)
+    pub fn condition_column(&self, info: &UserInfo) -> String {
+        if self.config.conditions.is_empty() {
+            String::new()
+        } else {
+            format!(",{}", self.condition(info).map_or("", |c| c.name))
+        }
+    }
+
+    /// Append a participant's condition to an output record (if the study has conditions)
+    pub fn push_condition(&self, info: &UserInfo, record: &mut Vec<String>) {
+        if !self.config.conditions.is_empty() {
+            record.push(self.condition(info).map_or("", |c| c.name).to_owned());
+        }
+    }
+
+    /// Completion code issued to a participant (derived from the study seed, so it can be checked later)
+    pub fn completion_code(&self, id: &str) -> String {
+        format!("{:08X}", user_seed(!self.config.sampling.seed, id) >> 32)
+    }
+
+    /// Path of one of this study's output files
+    pub fn output(&self, name: &str) -> PathBuf {
+        Path::new(self.config.dir).join(name)
+    }
+
+    /// Position of an episode in the surface list
+    pub fn index_of(&self, key: (Datestamp, FlowType, u32)) -> Option<usize> {
+        self.surfaces.binary_search_by_key(&key, |s| (s.date, s.flow, s.num)).ok()
+    }
+
+    /// Surfaces that may be sampled (those not excluded because of reports)
+    pub fn eligible(&self, reports: &HashMap<(Datestamp, FlowType, u32), ReportTally>) -> Vec<usize> {
+        (0..self.surfaces.len()).filter(|&i| {
+                                    let key = (self.surfaces[i].date, self.surfaces[i].flow, self.surfaces[i].num);
+                                    !reports.get(&key).map_or(false, |t| t.excluded(&self.config.sampling))
+                                })
+                                .collect()
+    }
+
+    /// Users whose data should be left out of exports
+    pub fn excluded_users(&self) -> HashSet<String> {
+        self.users.lock().unwrap()
+                  .iter()
+                  .filter(|&(_, info)| info.excluded())
+                  .map(|(id, _)| id.clone())
+                  .collect()
+    }
+
+    /// Read back all ratings submitted in this study, along with the user who submitted each
+    pub fn ratings(&self) -> Result<Vec<(SurfaceData, String)>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::RATINGS))?;
+        let questions = csv.headers()?.iter()
+                                      .skip(4)
+                                      .take(self.config.questionnaire.len())
+                                      .map(|s| s.to_lowercase())
+                                      .collect::<Vec<_>>();
+        let mut rows = vec![];
+        for row in csv.records() {
+            let mut row = row?;
+            let answers = row.iter()
+                             .skip(4)
+                             .take(questions.len())
+                             .map(|s| s.parse())
+                             .collect::<StdResult<Vec<_>,_>>()?;
+            row.truncate(4);
+            let row: SurfaceDataWithUser = row.deserialize(None)?;
+            let (mut surface, username) = row.without_user();
+            surface.ratings = questions.iter().cloned().zip(answers).collect();
+            rows.push((surface, username));
+        }
+        Ok(rows)
+    }
+
+    /// Read back all image reports submitted in this study, along with the user who submitted each
+    pub fn image_reports(&self) -> Result<Vec<(Report, String)>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::REPORTS))?;
+        let headers = csv.headers()?.iter()
+                                    .map(|s| s.split(' ').next().unwrap().to_lowercase())
+                                    .collect();
+        csv.set_headers(headers);
+        let mut rows = vec![];
+        for row in csv.deserialize() {
+            let row: ReportWithUser = row?;
+            rows.push(row.without_user());
+        }
+        Ok(rows)
+    }
+
+    /// Read back all pairwise comparisons submitted in this study
+    pub fn pairs(&self) -> Result<Vec<PairRecord>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::PAIRS))?;
+        let rows = csv.deserialize().collect::<StdResult<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Read back all rankings submitted in this study
+    pub fn rankings(&self) -> Result<Vec<RankingRecord>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::RANKINGS))?;
+        let rows = csv.deserialize().collect::<StdResult<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Read back all category labels submitted in this study
+    pub fn labels(&self) -> Result<Vec<LabelRecord>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::LABELS))?;
+        let rows = csv.deserialize().collect::<StdResult<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Read back all attention check results in this study
+    pub fn checks(&self) -> Result<Vec<CheckRecord>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::CHECKS))?;
+        let rows = csv.deserialize().collect::<StdResult<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Read back the trial log of this study
+    pub fn trials(&self) -> Result<Vec<TrialRecord>> {
+        let mut csv = csv::Reader::from_path(self.output(settings::TRIALS))?;
+        let rows = csv.deserialize().collect::<StdResult<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Pairwise comparisons implied by the rankings (each surface beats every surface ranked below it)
+    pub fn implied_pairs(&self) -> Result<Vec<PairRecord>> {
+        let mut trials = BTreeMap::<(String, usize), Vec<RankingRecord>>::new();
+        for row in self.rankings()? {
+            trials.entry((row.user.clone(), row.trial)).or_insert_with(Vec::new).push(row);
+        }
+
+        let mut pairs = vec![];
+        for (_, mut trial) in trials {
+            trial.sort_by_key(|r| r.rank);
+            for (i, a) in trial.iter().enumerate() {
+                for b in &trial[i + 1..] {
+                    pairs.push(PairRecord {
+                        user: a.user.clone(),
+                        a_date: a.date, a_flow: a.flow, a_num: a.num,
+                        b_date: b.date, b_flow: b.flow, b_num: b.num,
+                        question: a.question.clone(),
+                        winner: "A".into()
+                    });
+                }
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Reassign (or with `None`, delete) all of a participant's rows in the output files, then reload
+    /// the study's state from them
+    ///
+    /// Trial orders are deleted rather than reassigned, since they are derived from the participant ID.
+    pub fn rewrite_participant(&self, id: &str, replacement: Option<&str>) -> Result<usize> {
+        // hold the users table so that no rows are appended meanwhile
+        let mut users = self.users.lock().unwrap();
+
+        let mut changed = 0;
+        for &name in PARTICIPANT_FILES {
+            let path = self.output(name);
+            let tmp = path.with_extension("csv.tmp");
+            {
+                let mut reader = csv::Reader::from_path(&path)?;
+                let mut writer = csv::Writer::from_path(&tmp)?;
+                let headers = reader.headers()?.clone();
+                writer.write_record(&headers)?;
+                for row in reader.records() {
+                    let row = row?;
+                    if &row[0] != id {
+                        writer.write_record(&row)?;
+                        continue;
+                    }
+
+                    changed += 1;
+                    match replacement {
+                        Some(new) if name != settings::ORDERS => {
+                            let mut fields = row.iter().map(String::from).collect::<Vec<_>>();
+                            fields[0] = new.to_owned();
+                            writer.write_record(&fields)?;
+                        }
+                        _ => {}
+                    }
+                }
+                writer.flush()?;
+            }
+            fs::rename(&tmp, &path)?;
+        }
+
+        let mut all_surfaces = self.surfaces.clone();
+        for surf in &self.practice {
+            if self.index_of((surf.date, surf.flow, surf.num)).is_none() {
+                all_surfaces.push(surf.clone());
+            }
+        }
+        all_surfaces.sort_by_key(|s| (s.date, s.flow, s.num));
+        let fresh = Study::load(self.config, &all_surfaces)?;
+        *users = fresh.users.into_inner().unwrap();
+        *self.reports.lock().unwrap() = fresh.reports.into_inner().unwrap();
+        *self.moments.lock().unwrap() = fresh.moments.into_inner().unwrap();
+        *self.comparisons.lock().unwrap() = fresh.comparisons.into_inner().unwrap();
+
+        Ok(changed)
+    }
+
+    /// Open one of this study's output files for appending a row
+    pub fn append(&self, name: &str) -> Result<File> {
+        Ok(OpenOptions::new().append(true).open(self.output(name))?)
+    }
+
+    /// Select the study's surfaces and restore its state from the output files
+    pub fn load(config: &'static StudyConfig, all_surfaces: &[SurfaceData]) -> Result<Study> {
+        println!("Loading study {:?}...", config.id);
+        fs::create_dir_all(config.dir)?;
+        let dir = Path::new(config.dir);
+
+        let surfaces = all_surfaces.iter()
+                                   .filter(|s| config.filter.matches(s))
+                                   .cloned()
+                                   .collect::<Vec<_>>();
+        println!("\t{} surfaces", surfaces.len());
+
+        let practice = config.practice.iter()
+                                      .map(|&(date, flow, num)| {
+                                          all_surfaces.iter()
+                                                      .find(|s| (s.date, s.flow, s.num) == (date, flow, num))
+                                                      .cloned()
+                                                      .ok_or_else(|| format!("practice surface {}/{}/{} not found", date, flow, num).into())
+                                      })
+                                      .collect::<Result<Vec<_>>>()?;
+
+        let mut users = HashMap::<String, UserInfo>::new();
+        let mut reports = HashMap::<(Datestamp, FlowType, u32), ReportTally>::new();
+        let mut moments = HashMap::new();
+
+        let mut headers = vec!["User", "Date", "Flow type", "Number"].into_iter().map(String::from).collect::<Vec<_>>();
+        headers.extend(config.questionnaire.iter().map(|q| capitalize(q.short)));
+        if !config.conditions.is_empty() {
+            headers.push("Condition".into());
+        }
+        ::output_file(dir.join(settings::RATINGS),
+                      &headers,
+                      |mut csv| {
+                          let ratings = csv.headers()?.iter()
+                                                      .skip(4)
+                                                      .take(config.questionnaire.len())
+                                                      .map(|s| s.to_lowercase())
+                                                      .collect::<Vec<_>>();
+                          for row in csv.records() {
+                              let mut row = row?;
+                              let answers = row.iter()
+                                               .skip(4)
+                                               .take(config.questionnaire.len())
+                                               .map(|s| s.parse())
+                                               .collect::<StdResult<Vec<_>,_>>()?;
+                              row.truncate(4);
+                              let row: SurfaceDataWithUser = row.deserialize(None)?;
+                              let (mut surface, username) = row.without_user();
+                              tally_ratings(&mut moments, (surface.date, surface.flow, surface.num), &answers);
+                              surface.ratings = ratings.iter()
+                                                       .cloned()
+                                                       .zip(answers)
+                                                       .collect();
+                              let user_info = users.entry(username).or_insert_with(Default::default);
+                              user_info.seen.push((surface.date, surface.flow, surface.num));
+                              reports.entry((surface.date, surface.flow, surface.num)).or_insert_with(Default::default).views += 1;
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::PRACTICE),
+                      &headers,
+                      |mut csv| {
+                          for row in csv.records() {
+                              let row = row?;
+                              let user_info = users.entry(row[0].to_owned()).or_insert_with(Default::default);
+                              user_info.practiced += 1;
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::REPORTS),
+                      &with_condition(config, &["User", "Date", "Flow type", "Number", "Dark", "Bright", "Blurry", "Grainy"]),
+                      |mut csv| {
+                          unborrow!(csv.set_headers(csv.headers().unwrap()
+                                                       .iter()
+                                                       .map(|s| s.split(' ').next().unwrap().to_lowercase())
+                                                       .collect()));
+                          for row in csv.deserialize() {
+                              let row: ReportWithUser = row?;
+                              let (report, username) = row.without_user();
+                              let user_info = users.entry(username).or_insert_with(Default::default);
+                              user_info.seen.push((report.date, report.flow, report.num));
+                              let tally = reports.entry((report.date, report.flow, report.num)).or_insert_with(Default::default);
+                              tally.reports += 1;
+                              tally.views += 1;
+                          }
+                          Ok(())
+                      })?;
+        let mut check_headers = vec!["User", "Date", "Flow type", "Number", "Kind", "Passed"].into_iter().map(String::from).collect::<Vec<_>>();
+        check_headers.extend(config.questionnaire.iter().map(|q| capitalize(q.short)));
+        if !config.conditions.is_empty() {
+            check_headers.push("Condition".into());
+        }
+        ::output_file(dir.join(settings::CHECKS),
+                      &check_headers,
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: CheckRecord = row?;
+                              let user_info = users.entry(row.user).or_insert_with(Default::default);
+                              // gold answers are only in this file
+                              if row.kind == "gold" {
+                                  user_info.seen.push((row.date, row.flow, row.num));
+                              }
+                              if row.passed {
+                                  user_info.checks_passed += 1;
+                              } else {
+                                  user_info.checks_failed += 1;
+                              }
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::EXCLUSIONS),
+                      &["Date", "Flow type", "Number", "Override"],
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: ExclusionRecord = row?;
+                              let tally = reports.entry((row.date, row.flow, row.num)).or_insert_with(Default::default);
+                              tally.exclude = Exclusion::exclude(&row.action)?;
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::RATER_EXCLUSIONS),
+                      &["User", "Flags"],
+                      |mut csv| {
+                          for row in csv.records() {
+                              users.entry(row?[0].to_owned()).or_insert_with(Default::default).listed = true;
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::ORDERS),
+                      &with_condition(config, &["User", "Seed", "Assignment"]),
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: OrderRecord = row?;
+                              let order = Order::new(config, &row.user, row.assignment, &surfaces);
+                              if order.seed != row.seed {
+                                  bail!("seed mismatch for user {} (was the study seed changed?)", row.user);
+                              }
+                              let user_info = users.entry(row.user).or_insert_with(Default::default);
+                              user_info.order = Some(order);
+                              user_info.condition = config.conditions.iter().position(|c| Some(c.name) == row.condition.as_ref().map(|s| &s[..]));
+                          }
+                          Ok(())
+                      })?;
+        let mut comparisons = HashMap::new();
+        ::output_file(dir.join(settings::PAIRS),
+                      &with_condition(config, &["User", "Date A", "Flow type A", "Number A", "Date B", "Flow type B", "Number B", "Question", "Winner"]),
+                      |mut csv| {
+                          let index_of = |key: (Datestamp, FlowType, u32)| surfaces.binary_search_by_key(&key, |s: &SurfaceData| (s.date, s.flow, s.num)).ok();
+                          for row in csv.deserialize() {
+                              let row: PairRecord = row?;
+                              if let (Some(a), Some(b)) = (index_of((row.a_date, row.a_flow, row.a_num)), index_of((row.b_date, row.b_flow, row.b_num))) {
+                                  let pair = if a < b { (a, b) } else { (b, a) };
+                                  let user_info = users.entry(row.user).or_insert_with(Default::default);
+                                  if user_info.compared.insert(pair) {
+                                      *comparisons.entry(pair).or_insert(0) += 1;
+                                  }
+                              }
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::RANKINGS),
+                      &with_condition(config, &["User", "Trial", "Question", "Date", "Flow type", "Number", "Position", "Rank"]),
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: RankingRecord = row?;
+                              let user_info = users.entry(row.user).or_insert_with(Default::default);
+                              user_info.rankings = cmp::max(user_info.rankings, row.trial + 1);
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::LABELS),
+                      &with_condition(config, &["User", "Date", "Flow type", "Number", "Category", "Description"]),
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: LabelRecord = row?;
+                              reports.entry((row.date, row.flow, row.num)).or_insert_with(Default::default).views += 1;
+                              let user_info = users.entry(row.user).or_insert_with(Default::default);
+                              user_info.seen.push((row.date, row.flow, row.num));
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::ONBOARDING),
+                      &with_condition(config, &["User", "Step", "Answer", "Time"]),
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: OnboardingRecord = row?;
+                              let user_info = users.entry(row.user).or_insert_with(Default::default);
+                              match &row.step[..] {
+                                  "consent" => user_info.consent = Some((row.answer, row.time)),
+                                  "instructions" => user_info.instructed = true,
+                                  _ => { user_info.demographics.insert(row.step, row.answer); }
+                              }
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::COMPLETIONS),
+                      &with_condition(config, &["User", "Worker", "Code", "Time"]),
+                      |mut csv| {
+                          for row in csv.deserialize() {
+                              let row: CompletionRecord = row?;
+                              users.entry(row.user).or_insert_with(Default::default).completed = true;
+                          }
+                          Ok(())
+                      })?;
+        ::output_file(dir.join(settings::TRIALS),
+                      &with_condition(config, &["User", "Date", "Flow type", "Number", "Response", "Seed", "Position", "Time"]),
+                      |_| Ok(()))?;
+
+        Ok(Study {
+            config,
+            surfaces,
+            practice,
+            users: Mutex::new(users),
+            reports: Mutex::new(reports),
+            moments: Mutex::new(moments),
+            comparisons: Mutex::new(comparisons),
+        })
+    }
+}
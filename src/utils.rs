@@ -1,5 +1,8 @@
 use std::path::Path;
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::http::ContentType;
+use rocket::response::content::Content;
 
 use structs::{Datestamp, FlowType};
 
@@ -8,6 +11,40 @@ pub fn elapsed(start: SystemTime) -> String {
     format!("{}.{}s", dur.as_secs(), dur.subsec_nanos() / 1_000_000)
 }
 
+/// Seconds since the Unix epoch, for timestamping output rows
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// Calendar date (UTC) of a timestamp, as YYYY-MM-DD
+pub fn day_of(timestamp: u64) -> String {
+    // civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = (timestamp / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Serve an export as a CSV file
+pub fn csv_response(body: String) -> Content<String> {
+    Content(ContentType::new("text", "csv"), body)
+}
+
+/// Uppercase the first letter of a string (e.g. for CSV column names)
+pub fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
 pub fn extract_path(path: &Path) -> (Datestamp, FlowType, u32) {
     macro_rules! x { ($e:expr) => { $e.and_then(|s| s.as_os_str().to_str()).and_then(|s| s.parse().ok()).unwrap() } }
 